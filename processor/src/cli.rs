@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/// Penny auction data pipeline.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Config file holding the database url and the [default.pipeline] parameters
+    #[arg(long, global = true, default_value = "penny.toml")]
+    pub config: PathBuf,
+
+    /// Directory containing the raw adf and bdf parquet snapshots
+    #[arg(long, global = true, default_value = "./data/raw")]
+    pub raw_dir: PathBuf,
+
+    /// Directory the processed df.parquet, user_stats.parquet and quality.parquet are written to
+    #[arg(long, global = true, default_value = "./data")]
+    pub data_dir: PathBuf,
+
+    /// Directory the train/val/test split files are written to
+    #[arg(long, global = true, default_value = "./data/split")]
+    pub split_dir: PathBuf,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Rebuild the auction and bid tables from the raw parquet files
+    Import,
+    /// Export auctions and bids from the database into the raw parquet files
+    Export(ExportArgs),
+    /// Report auctions that would be dropped by data quality checks
+    Quality,
+    /// Build df.parquet from the raw parquet files
+    Process,
+    /// Split df.parquet into the train/val/test frames
+    Split,
+    /// Run several stages back to back
+    All {
+        /// Stages to run, in pipeline order
+        #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Stage::Export, Stage::Process, Stage::Split])]
+        stages: Vec<Stage>,
+
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+}
+
+#[derive(Args, Clone, Debug, Default)]
+pub struct ExportArgs {
+    /// Drop and recreate the database before exporting. This deletes all collected data!
+    #[arg(long)]
+    pub recreate: bool,
+
+    /// Re-export everything instead of appending only new auctions and bids to the snapshot
+    #[arg(long)]
+    pub full: bool,
+
+    /// Export every auction column instead of only the ones used by the model
+    #[arg(long)]
+    pub all_columns: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Import,
+    Export,
+    Quality,
+    Process,
+    Split,
+}
+
+impl Command {
+    /// Stages selected by this command, sorted into pipeline order.
+    pub fn stages(&self) -> Vec<Stage> {
+        match self {
+            Command::Import => vec![Stage::Import],
+            Command::Export(_) => vec![Stage::Export],
+            Command::Quality => vec![Stage::Quality],
+            Command::Process => vec![Stage::Process],
+            Command::Split => vec![Stage::Split],
+            Command::All { stages, .. } => {
+                let mut stages = stages.clone();
+                stages.sort();
+                stages.dedup();
+                stages
+            }
+        }
+    }
+
+    /// Export options, defaulted for commands that do not export.
+    pub fn export_args(&self) -> ExportArgs {
+        match self {
+            Command::Export(args) | Command::All { export: args, .. } => args.clone(),
+            _ => ExportArgs::default(),
+        }
+    }
+}
+
+/// Locations of every file the pipeline reads or writes.
+#[derive(Clone, Debug)]
+pub struct Paths {
+    pub raw_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub split_dir: PathBuf,
+}
+
+impl Paths {
+    pub fn df(&self) -> PathBuf {
+        self.data_dir.join("df.parquet")
+    }
+
+    pub fn user_stats(&self) -> PathBuf {
+        self.data_dir.join("user_stats.parquet")
+    }
+
+    pub fn quality(&self) -> PathBuf {
+        self.data_dir.join("quality.parquet")
+    }
+
+    /// Resolved configuration `process` built `df.parquet` with.
+    pub fn df_config(&self) -> PathBuf {
+        self.data_dir.join("pipeline.toml")
+    }
+
+    pub fn split_file(&self, kind: &str, id: &str) -> PathBuf {
+        self.split_dir.join(format!("{kind}_{id}.parquet"))
+    }
+
+    pub fn split_config(&self) -> PathBuf {
+        self.split_dir.join("pipeline.toml")
+    }
+
+    /// Copy of [`Paths::df_config`], so the split files can be traced back without `data/`.
+    pub fn split_df_config(&self) -> PathBuf {
+        self.split_dir.join("process.toml")
+    }
+
+    /// Paths of walk-forward fold `k`, whose split files go in their own directory.
+    pub fn fold(&self, k: usize) -> Paths {
+        Paths {
+            split_dir: self.split_dir.join(format!("fold_{k}")),
+            ..self.clone()
+        }
+    }
+
+    pub fn sequence_metadata(&self) -> PathBuf {
+        self.split_dir.join("sequences.toml")
+    }
+}
+
+impl From<&Cli> for Paths {
+    fn from(cli: &Cli) -> Self {
+        Paths {
+            raw_dir: cli.raw_dir.clone(),
+            data_dir: cli.data_dir.clone(),
+            split_dir: cli.split_dir.clone(),
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use std::env;
+use std::path::Path;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use polars::prelude::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ProcessorError;
+use crate::{ops, tz};
+
+/// Feature engineering parameters, read from the `[default.pipeline]` section of penny.toml.
+///
+/// Every value can be overridden with a `PENNY_PIPELINE_` environment variable, nested keys are
+/// separated by `__` (e.g. `PENNY_PIPELINE_SPLIT__TRAIN=0.7`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Number of bids (the current one included) in each time series row.
+    pub lookback: usize,
+    /// Only bids placed after this time (in `source_timezone`) are exported from the database.
+    pub bids_since: NaiveDateTime,
+    /// Timezone of the naive timestamps stored in the database. Exported files are in UTC.
+    pub source_timezone: String,
+    /// Timezone of the auction site, time of day features use its wall clock.
+    pub site_timezone: String,
+    /// Version of the engineered auction features, older versions stay available for old models.
+    pub feature_set: FeatureSet,
+    /// Cast raw numeric columns of the wrong type instead of rejecting them.
+    pub coerce_types: bool,
+    /// Price of a single bid in pennies.
+    pub bid_cost: u64,
+    pub repair: RepairConfig,
+    pub user_stats: UserStatsConfig,
+    pub clock: ClockConfig,
+    pub competition: CompetitionConfig,
+    pub split: SplitConfig,
+    pub output: OutputConfig,
+}
+
+/// Versions of the auction feature encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeatureSet {
+    /// Start hour and minute both encoded with a period of 23. Hours are read on the
+    /// `site_timezone` clock, so this reproduces the original encoding only when `site_timezone`
+    /// equals `source_timezone`.
+    V1,
+    /// Start hour, minute, weekday and time of day encoded with their true periods.
+    #[default]
+    V2,
+}
+
+/// Gap repair for auctions with a few missed bids.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepairConfig {
+    /// Auctions missing fewer than this share of their bids get placeholder bids instead of being
+    /// dropped, 0 disables the repair.
+    pub max_missing_ratio: f64,
+}
+
+/// Countdown timer rules. Every bid resets the auction timer to the `reset_to` of the price band
+/// the new price falls in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// Bands ordered by `from_price`, each covering prices up to the next band's start.
+    pub bands: Vec<ClockBand>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockBand {
+    /// Lowest price in pennies the band applies to.
+    pub from_price: u64,
+    /// Timer value after a bid, calibrated from the time between the final bid and the auction
+    /// end when left out.
+    pub reset_to: Option<String>,
+}
+
+/// Windows for the auction level competition features.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompetitionConfig {
+    /// Distinct bidders are counted over this many of the latest bids.
+    pub last_bids: usize,
+    /// Distinct bidders are also counted over the bids placed within this long.
+    pub last: String,
+    /// Bidders with at least this many bids in the auction count as committed.
+    pub min_bids: usize,
+}
+
+/// Windows used to aggregate each user's bidding history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserStatsConfig {
+    /// How far back each bid looks into its user's history, one set of stats per window. A
+    /// duration like `24h`, or `lifetime` for everything before the bid.
+    pub windows: Vec<String>,
+}
+
+/// Chronological train/val/test split. The test split gets whatever is left after train and val.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitConfig {
+    /// Auctions starting within this long of the first bid are skipped so user stats can warm up.
+    pub warmup: String,
+    pub train: f64,
+    pub val: f64,
+    pub folds: FoldConfig,
+}
+
+/// Walk-forward cross-validation folds, written next to the regular split.
+///
+/// The validation windows of the `k` folds are consecutive and end where the test split starts,
+/// each fold trains on the auctions before its window. Shares are of all auctions, like the split
+/// ratios, and the folds must fit in the train and val splits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FoldConfig {
+    /// Number of folds, 0 disables them.
+    pub k: usize,
+    pub window: FoldWindow,
+    /// Share of the auctions in the sliding training window, unused for expanding windows.
+    pub train: f64,
+    /// Share of the auctions in each validation window.
+    pub val: f64,
+    /// Training auctions ending within this long of the validation window are dropped, so user
+    /// stats computed over their bids do not overlap the validation period. `0s` disables it.
+    pub purge: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldWindow {
+    /// Train on every auction before the validation window.
+    #[default]
+    Expanding,
+    /// Train on a fixed number of auctions right before the validation window.
+    Sliding,
+}
+
+/// Layout of the split files handed to the trainer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    /// Longest sequence written by the `list` format, longer auctions are cut into chunks.
+    pub max_len: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// `tdf`, `mdf` and `y` files with one row per bid and `lookback` shifted copies of each feature.
+    #[default]
+    Shifted,
+    /// `seq` files with one row per auction (chunk) and a list column per feature.
+    List,
+}
+
+/// Describes the `seq` files so the trainer can pad and mask them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceMetadata {
+    pub max_len: usize,
+    /// Column holding the number of valid steps in each row, steps past it are padding.
+    pub length_column: String,
+    pub features: Vec<String>,
+    pub meta: Vec<String>,
+    pub labels: Vec<String>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            lookback: 9,
+            bids_since: NaiveDate::from_ymd_opt(2023, 1, 1)
+                .and_then(|d| d.and_hms_opt(7, 0, 0))
+                .expect("valid date"),
+            source_timezone: tz::UTC.into(),
+            site_timezone: tz::UTC.into(),
+            feature_set: FeatureSet::default(),
+            coerce_types: false,
+            bid_cost: 15,
+            repair: RepairConfig::default(),
+            user_stats: UserStatsConfig::default(),
+            clock: ClockConfig::default(),
+            competition: CompetitionConfig::default(),
+            split: SplitConfig::default(),
+            output: OutputConfig::default(),
+        }
+    }
+}
+
+impl Default for UserStatsConfig {
+    fn default() -> Self {
+        UserStatsConfig {
+            windows: vec!["4d".into()],
+        }
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            bands: vec![ClockBand {
+                from_price: 0,
+                reset_to: None,
+            }],
+        }
+    }
+}
+
+impl Default for CompetitionConfig {
+    fn default() -> Self {
+        CompetitionConfig {
+            last_bids: 10,
+            last: "30s".into(),
+            min_bids: 3,
+        }
+    }
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        SplitConfig {
+            warmup: "4d1h".into(),
+            train: 0.6,
+            val: 0.2,
+            folds: FoldConfig::default(),
+        }
+    }
+}
+
+impl Default for FoldConfig {
+    fn default() -> Self {
+        FoldConfig {
+            k: 0,
+            window: FoldWindow::default(),
+            train: 0.4,
+            val: 0.1,
+            purge: "4d".into(),
+        }
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            format: OutputFormat::default(),
+            max_len: 64,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn load(path: &Path) -> Result<Self, ProcessorError> {
+        let config: PipelineConfig = Figment::from(Serialized::defaults(PipelineConfig::default()))
+            .merge(Figment::from(Toml::file(path)).focus("default.pipeline"))
+            .merge(Env::prefixed("PENNY_PIPELINE_").split("__"))
+            .extract()?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ProcessorError> {
+        if self.lookback == 0 {
+            return Err(invalid("lookback must be at least 1"));
+        }
+        if self.output.max_len == 0 {
+            return Err(invalid("output.max_len must be at least 1"));
+        }
+        if self.bid_cost == 0 {
+            return Err(invalid("bid_cost must be at least 1 penny"));
+        }
+
+        for (key, value) in [
+            ("source_timezone", &self.source_timezone),
+            ("site_timezone", &self.site_timezone),
+        ] {
+            if !tz::is_valid(value) {
+                return Err(invalid(format!("{key} has unknown timezone `{value}`")));
+            }
+        }
+
+        if !(0. ..1.).contains(&self.repair.max_missing_ratio) {
+            return Err(invalid(format!(
+                "repair.max_missing_ratio must lie in [0, 1), got {}",
+                self.repair.max_missing_ratio
+            )));
+        }
+
+        if self.user_stats.windows.is_empty() {
+            return Err(invalid("user_stats.windows needs at least one window"));
+        }
+        for (i, window) in self.user_stats.windows.iter().enumerate() {
+            if self.user_stats.windows[..i].contains(window) {
+                return Err(invalid(format!(
+                    "user_stats.windows lists `{window}` twice"
+                )));
+            }
+            if window != ops::LIFETIME {
+                check_positive_duration("user_stats.windows", window)?;
+            }
+        }
+        let bands = &self.clock.bands;
+        if bands.is_empty() || bands.windows(2).any(|w| w[0].from_price >= w[1].from_price) {
+            return Err(invalid(
+                "clock.bands must hold at least one band, ordered by increasing from_price",
+            ));
+        }
+        for reset_to in bands.iter().filter_map(|band| band.reset_to.as_ref()) {
+            check_positive_duration("clock.bands.reset_to", reset_to)?;
+        }
+
+        if self.competition.last_bids == 0 || self.competition.min_bids == 0 {
+            return Err(invalid(
+                "competition.last_bids and competition.min_bids must be at least 1",
+            ));
+        }
+        check_positive_duration("competition.last", &self.competition.last)?;
+
+        check_duration("split.warmup", &self.split.warmup)?;
+        check_duration("split.folds.purge", &self.split.folds.purge)?;
+
+        let SplitConfig { train, val, .. } = self.split;
+        let before_test = train + val;
+        if !(0. ..=1.).contains(&train) || !(0. ..=1.).contains(&val) || before_test > 1. {
+            return Err(invalid(format!(
+                "split ratios must lie in [0, 1] and sum to at most 1, got train {train} and val {val}"
+            )));
+        }
+
+        let FoldConfig {
+            k,
+            window,
+            train,
+            val,
+            ..
+        } = self.split.folds;
+        let used = k as f64 * val
+            + if window == FoldWindow::Sliding {
+                train
+            } else {
+                0.
+            };
+        if k > 0 && (!(0. ..=1.).contains(&train) || val <= 0. || used >= before_test) {
+            return Err(invalid(format!(
+                "{k} folds of val {val} (and a sliding train window of {train}) do not fit in the train and val share of {before_test}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Write the resolved configuration so a dataset can be traced back to the parameters that
+    /// produced it.
+    pub fn write(&self, path: &Path) -> Result<(), ProcessorError> {
+        write_toml(self, path)
+    }
+}
+
+impl SequenceMetadata {
+    pub fn write(&self, path: &Path) -> Result<(), ProcessorError> {
+        write_toml(self, path)
+    }
+}
+
+impl SplitConfig {
+    pub fn warmup(&self) -> chrono::Duration {
+        to_chrono(&self.warmup)
+    }
+}
+
+impl CompetitionConfig {
+    pub fn last(&self) -> chrono::Duration {
+        to_chrono(&self.last)
+    }
+}
+
+impl ClockBand {
+    pub fn reset_to(&self) -> Option<chrono::Duration> {
+        self.reset_to.as_deref().map(to_chrono)
+    }
+}
+
+impl FoldConfig {
+    pub fn purge(&self) -> chrono::Duration {
+        to_chrono(&self.purge)
+    }
+}
+
+/// Database url from the `DB_URL` env var, falling back to `default.databases.penny.url`.
+pub fn database_url(path: &Path) -> Result<String, ProcessorError> {
+    match env::var("DB_URL") {
+        Ok(v) => Ok(v),
+        Err(_e) => Ok(Figment::from(Toml::file(path))
+            .extract_inner::<String>("default.databases.penny.url")?),
+    }
+}
+
+fn to_chrono(duration: &str) -> chrono::Duration {
+    chrono::Duration::nanoseconds(Duration::parse(duration).duration_ns())
+}
+
+fn write_toml<T: Serialize>(value: &T, path: &Path) -> Result<(), ProcessorError> {
+    let contents = toml::to_string_pretty(value)
+        .map_err(|err| invalid(format!("could not serialize {}: {err}", path.display())))?;
+    std::fs::write(path, contents).map_err(|source| ProcessorError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn invalid(msg: impl Into<String>) -> ProcessorError {
+    ProcessorError::InvalidConfig(msg.into())
+}
+
+/// Polars panics on malformed duration strings, so reject them while loading instead. None of the
+/// configured durations can be negative.
+fn check_duration(key: &str, value: &str) -> Result<(), ProcessorError> {
+    const UNITS: [&str; 10] = ["ns", "us", "ms", "s", "m", "h", "d", "w", "mo", "y"];
+
+    if value.starts_with('-') {
+        return Err(invalid(format!("{key} has negative duration `{value}`")));
+    }
+    let mut rest = value;
+    if rest.is_empty() {
+        return Err(invalid(format!("{key} is an empty duration")));
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let tail = &rest[digits..];
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        if digits == 0 || !UNITS.contains(&&tail[..unit_len]) {
+            return Err(invalid(format!("{key} has invalid duration `{value}`")));
+        }
+        rest = &tail[unit_len..];
+    }
+
+    Ok(())
+}
+
+/// Like [`check_duration`], also rejecting `0s` for windows and timers that must span some time.
+fn check_positive_duration(key: &str, value: &str) -> Result<(), ProcessorError> {
+    check_duration(key, value)?;
+    if Duration::parse(value).is_zero() {
+        return Err(invalid(format!(
+            "{key} must be a positive duration, got `{value}`"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_windows(windows: &[&str]) -> PipelineConfig {
+        PipelineConfig {
+            user_stats: UserStatsConfig {
+                windows: windows.iter().map(|&w| w.to_owned()).collect(),
+            },
+            ..PipelineConfig::default()
+        }
+    }
+
+    #[test]
+    fn windows_must_be_positive() {
+        assert!(with_windows(&["1h", "1d12h", ops::LIFETIME])
+            .validate()
+            .is_ok());
+        for window in ["-4d", "0s", "0d0h", "4x", ""] {
+            assert!(with_windows(&[window]).validate().is_err(), "{window}");
+        }
+    }
+
+    #[test]
+    fn clock_resets_must_be_positive() {
+        for (reset_to, valid) in [("2m", true), ("-2m", false), ("0s", false)] {
+            let config = PipelineConfig {
+                clock: ClockConfig {
+                    bands: vec![ClockBand {
+                        from_price: 0,
+                        reset_to: Some(reset_to.into()),
+                    }],
+                },
+                ..PipelineConfig::default()
+            };
+            assert_eq!(config.validate().is_ok(), valid, "{reset_to}");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use polars::prelude::PolarsError;
+use rust_decimal::Decimal;
+use sea_orm::DbErr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProcessorError {
+    #[error("could not access {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("could not read or write {}: {source}", path.display())]
+    Parquet { path: PathBuf, source: PolarsError },
+    #[error("unexpected frame schema: {0}")]
+    Schema(PolarsError),
+    #[error("{name} does not match the expected schema: {}", problems.join("; "))]
+    SchemaValidation { name: String, problems: Vec<String> },
+    #[error("{} is not row aligned with {}", actual.display(), expected.display())]
+    Misaligned { expected: PathBuf, actual: PathBuf },
+    #[error("cannot extend the snapshot {}: {reason}", path.display())]
+    Snapshot { path: PathBuf, reason: String },
+    #[error(transparent)]
+    Polars(PolarsError),
+    #[error("could not connect to database `{database}`: {source}")]
+    DbConnection { database: String, source: DbErr },
+    #[error("database `{database}` does not exist and could not be created: {source}")]
+    DbMissing { database: String, source: DbErr },
+    #[error("database url `{0}` does not name a database")]
+    DbUrl(String),
+    #[error("database error: {0}")]
+    Db(#[from] DbErr),
+    #[error("could not load configuration: {0}")]
+    Config(#[from] figment::Error),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("{0} does not fit in a penny amount")]
+    PennyOverflow(Decimal),
+}
+
+impl From<PolarsError> for ProcessorError {
+    fn from(err: PolarsError) -> Self {
+        // Missing or renamed columns surface deep inside a lazy plan, call them out explicitly.
+        match err {
+            PolarsError::ColumnNotFound(_)
+            | PolarsError::SchemaFieldNotFound(_)
+            | PolarsError::SchemaMismatch(_)
+            | PolarsError::StructFieldNotFound(_) => ProcessorError::Schema(err),
+            err => ProcessorError::Polars(err),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use polars::io::parquet::BatchedWriter;
+use polars::prelude::*;
+
+use crate::error::ProcessorError;
+
+pub fn create_dir(path: &Path) -> Result<(), ProcessorError> {
+    std::fs::create_dir_all(path).map_err(|source| ProcessorError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+pub fn copy_file(from: &Path, to: &Path) -> Result<(), ProcessorError> {
+    std::fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|source| ProcessorError::Io {
+            path: from.to_path_buf(),
+            source,
+        })
+}
+
+pub fn read_parquet(path: &Path) -> Result<DataFrame, ProcessorError> {
+    read_parquet_columns(path, None)
+}
+
+/// Read a parquet file, only loading `columns` when given.
+pub fn read_parquet_columns(
+    path: &Path,
+    columns: Option<Vec<String>>,
+) -> Result<DataFrame, ProcessorError> {
+    let file = File::open(path).map_err(|source| ProcessorError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    ParquetReader::new(file)
+        .with_columns(columns)
+        .finish()
+        .map_err(|source| ProcessorError::Parquet {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+pub fn read_parquet_schema(path: &Path) -> Result<Schema, ProcessorError> {
+    let file = File::open(path).map_err(|source| ProcessorError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    ParquetReader::new(file)
+        .schema()
+        .map_err(|source| ProcessorError::Parquet {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// Snapshot parts of `name` in `dir`, oldest first: the `{name}.parquet` base snapshot followed by
+/// the `{name}_0001.parquet`, `{name}_0002.parquet`, ... increments.
+///
+/// Returns just the base path when nothing has been written yet, so reading it reports the missing
+/// file.
+pub fn parquet_parts(dir: &Path, name: &str) -> Result<Vec<PathBuf>, ProcessorError> {
+    let base = dir.join(format!("{name}.parquet"));
+    if !dir.exists() {
+        return Ok(vec![base]);
+    }
+
+    let entries = std::fs::read_dir(dir).map_err(|source| ProcessorError::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+
+    let prefix = format!("{name}_");
+    let mut increments = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|source| ProcessorError::Io {
+                path: dir.to_path_buf(),
+                source,
+            })?
+            .path();
+        let is_increment = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .and_then(|f| f.strip_prefix(&prefix))
+            .and_then(|f| f.strip_suffix(".parquet"))
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        if is_increment {
+            increments.push(path);
+        }
+    }
+    increments.sort();
+
+    Ok(std::iter::once(base).chain(increments).collect())
+}
+
+/// Path of the next increment of `name` after `parts`.
+pub fn next_part(dir: &Path, name: &str, parts: &[PathBuf]) -> PathBuf {
+    dir.join(format!("{name}_{:04}.parquet", parts.len()))
+}
+
+/// Read every snapshot part into a single frame, oldest rows first.
+pub fn read_parquet_parts(
+    parts: &[PathBuf],
+    columns: Option<Vec<String>>,
+) -> Result<DataFrame, ProcessorError> {
+    let mut df: Option<DataFrame> = None;
+    for part in parts {
+        let part_df = read_parquet_columns(part, columns.clone())?;
+        match df.as_mut() {
+            Some(df) => {
+                df.vstack_mut(&part_df)
+                    .map_err(|source| ProcessorError::Parquet {
+                        path: part.clone(),
+                        source,
+                    })?;
+            }
+            None => df = Some(part_df),
+        }
+    }
+
+    df.ok_or_else(|| ProcessorError::Io {
+        path: PathBuf::new(),
+        source: std::io::ErrorKind::NotFound.into(),
+    })
+}
+
+pub fn write_parquet(path: &Path, df: &mut DataFrame) -> Result<(), ProcessorError> {
+    let mut file = File::create(path).map_err(|source| ProcessorError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    ParquetWriter::new(&mut file)
+        .finish(df)
+        .map_err(|source| ProcessorError::Parquet {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(())
+}
+
+/// Appends frames to a parquet file one row group at a time, so the whole file never has to be in
+/// memory at once.
+pub struct BatchWriter {
+    path: PathBuf,
+    writer: BatchedWriter<File>,
+    rows: usize,
+}
+
+impl BatchWriter {
+    pub fn new(path: &Path, schema: &Schema) -> Result<Self, ProcessorError> {
+        let file = File::create(path).map_err(|source| ProcessorError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let writer =
+            ParquetWriter::new(file)
+                .batched(schema)
+                .map_err(|source| ProcessorError::Parquet {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+
+        Ok(BatchWriter {
+            path: path.to_path_buf(),
+            writer,
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, df: &DataFrame) -> Result<(), ProcessorError> {
+        self.writer
+            .write_batch(df)
+            .map_err(|source| ProcessorError::Parquet {
+                path: self.path.clone(),
+                source,
+            })?;
+        self.rows += df.height();
+        Ok(())
+    }
+
+    /// Rows written so far.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn finish(mut self) -> Result<usize, ProcessorError> {
+        self.writer
+            .finish()
+            .map_err(|source| ProcessorError::Parquet {
+                path: self.path.clone(),
+                source,
+            })?;
+        Ok(self.rows)
+    }
+}
@@ -0,0 +1,238 @@
+use std::ops::Deref;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use entity::{auction, auction::Entity as Auction};
+use entity::{bid, bid::Entity as Bid};
+use migration::{Migrator, MigratorTrait};
+use polars::prelude::*;
+use rust_decimal::Decimal;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveValue::Set, DbConn, EntityTrait};
+use tracing::debug;
+
+use crate::error::ProcessorError;
+use crate::{files, load, ops, setup, tz};
+
+/// Rows per insert statement, keeps auction inserts well under the bind parameter limit.
+const IMPORT_BATCH_SIZE: usize = 1_000;
+
+/// Rebuild the `auction` and `bid` tables from the raw snapshot parts in `raw_dir`.
+///
+/// Rows that are already in the database are left untouched, so an import can be re-run safely.
+/// Timestamps are stored as wall clock times in `source_timezone`, like the export expects them.
+pub async fn import_data(
+    db_full_url: &str,
+    raw_dir: &Path,
+    source_timezone: &str,
+) -> Result<(), ProcessorError> {
+    let db = setup::set_up_db(db_full_url, false).await?;
+    Migrator::up(&db, None).await?;
+    debug!("Connected to database");
+
+    debug!("Loading auctions from file");
+    let adf = files::read_parquet_parts(&files::parquet_parts(raw_dir, "adf")?, None)?;
+    let adf = ops::adf_latest_snapshot(adf)?;
+    let adf = tz::frame_to_local(adf, &["start_time", "end_time"], source_timezone)?;
+    import_aucs(&db, &adf).await?;
+
+    debug!("Loading bids from file");
+    let bdf = files::read_parquet_parts(&files::parquet_parts(raw_dir, "bdf")?, None)?;
+    let bdf = ops::bdf_latest_snapshot(bdf)?;
+    let bdf = tz::frame_to_local(bdf, &["timestamp"], source_timezone)?;
+    import_bids(&db, &bdf).await?;
+
+    Ok(())
+}
+
+pub(crate) fn penny_to_dollar(p: u64) -> Decimal {
+    Decimal::new(p as i64, 2)
+}
+
+fn values<T: PolarsNumericType>(
+    df: &DataFrame,
+    name: &str,
+) -> Result<Vec<Option<T::Native>>, ProcessorError> {
+    let s = df.column(name)?.cast(&T::get_dtype())?;
+    let values = s.unpack::<T>()?.into_iter().collect();
+    Ok(values)
+}
+
+fn bools(df: &DataFrame, name: &str) -> Result<Vec<Option<bool>>, ProcessorError> {
+    Ok(df.column(name)?.bool()?.into_iter().collect())
+}
+
+fn strings(df: &DataFrame, name: &str) -> Result<Vec<Option<String>>, ProcessorError> {
+    Ok(df
+        .column(name)?
+        .utf8()?
+        .into_iter()
+        .map(|v| v.map(str::to_owned))
+        .collect())
+}
+
+fn datetimes(df: &DataFrame, name: &str) -> Result<Vec<Option<NaiveDateTime>>, ProcessorError> {
+    let ca = df.column(name)?.datetime()?;
+    let unit = ca.time_unit();
+    Ok(ca
+        .deref()
+        .into_iter()
+        .map(|t| t.and_then(|t| load::timestamp_to_naive(t, unit)))
+        .collect())
+}
+
+/// Columns only present in `--all-columns` snapshots read as all nulls otherwise.
+fn optional<T: Clone>(
+    df: &DataFrame,
+    name: &str,
+    read: impl Fn(&DataFrame, &str) -> Result<Vec<Option<T>>, ProcessorError>,
+) -> Result<Vec<Option<T>>, ProcessorError> {
+    if df.schema().contains(name) {
+        read(df, name)
+    } else {
+        Ok(vec![None; df.height()])
+    }
+}
+
+fn required<T>(value: Option<T>, column: &str, row: usize) -> Result<T, ProcessorError> {
+    value.ok_or_else(|| {
+        PolarsError::SchemaMismatch(format!("{column} is null in row {row}").into()).into()
+    })
+}
+
+fn aucs_from_df(adf: &DataFrame) -> Result<Vec<auction::ActiveModel>, ProcessorError> {
+    let ids = values::<Int32Type>(adf, "auction_id")?;
+    let start_times = datetimes(adf, "start_time")?;
+    let bin_prices = values::<UInt64Type>(adf, "bin_price")?;
+    let no_jumper_limits = values::<UInt64Type>(adf, "no_jumper_limit")?;
+    let exchangeables = bools(adf, "exchangeable")?;
+    let one_per_users = bools(adf, "one_per_user")?;
+    let no_re_entries = bools(adf, "no_re_entry")?;
+    let is_bindolences = bools(adf, "is_bindolence")?;
+    let percent_offs = values::<Int32Type>(adf, "percent_off")?;
+    let end_times = datetimes(adf, "end_time")?;
+
+    // The published dataset leaves these out, fall back to neutral values for the NOT NULL ones
+    let category_ids = optional(adf, "category_id", values::<Int32Type>)?;
+    let category_names = optional(adf, "category_name", strings)?;
+    let names = optional(adf, "name", strings)?;
+    let bin_old_prices = optional(adf, "bin_old_price", values::<UInt64Type>)?;
+    let product_ids = optional(adf, "product_id", values::<Int32Type>)?;
+    let is_dummies = optional(adf, "is_dummy_auction_for_search", bools)?;
+    let estimated_total_costs = optional(adf, "estimated_total_cost", values::<UInt64Type>)?;
+    let bids_placed_by_winners = optional(adf, "bids_placed_by_winner", strings)?;
+    let promo_prices = optional(adf, "price_after_promo_discount", values::<UInt64Type>)?;
+    let percent_off_promos = optional(adf, "percent_off_promo", strings)?;
+    let started_recordings = optional(adf, "penny_started_recording", bools)?;
+    let recorded_fulls = optional(adf, "penny_recorded_full", bools)?;
+
+    (0..adf.height())
+        .map(|i| {
+            let bin_price = required(bin_prices[i], "bin_price", i)?;
+            Ok(auction::ActiveModel {
+                id: Set(required(ids[i], "auction_id", i)?),
+                category_id: Set(category_ids[i].unwrap_or_default()),
+                category_name: Set(category_names[i].clone().unwrap_or_default()),
+                name: Set(names[i].clone().unwrap_or_default()),
+                buy_it_now_price: Set(penny_to_dollar(bin_price)),
+                buy_it_now_old_price: Set(penny_to_dollar(bin_old_prices[i].unwrap_or(bin_price))),
+                product_id: Set(product_ids[i].unwrap_or_default()),
+                one_per_user: Set(required(one_per_users[i], "one_per_user", i)?),
+                is_dummy_auction_for_search: Set(is_dummies[i].unwrap_or_default()),
+                no_re_entry: Set(required(no_re_entries[i], "no_re_entry", i)?),
+                no_jumper_limit: Set(penny_to_dollar(required(
+                    no_jumper_limits[i],
+                    "no_jumper_limit",
+                    i,
+                )?)),
+                exchangeable: Set(required(exchangeables[i], "exchangeable", i)?),
+                start_time: Set(required(start_times[i], "start_time", i)?),
+                estimated_total_cost: Set(estimated_total_costs[i].map(penny_to_dollar)),
+                bids_placed_by_winner: Set(bids_placed_by_winners[i].clone()),
+                price_after_promo_discount: Set(promo_prices[i].map(penny_to_dollar)),
+                percent_off_promo: Set(percent_off_promos[i].clone()),
+                percent_off: Set(percent_offs[i]),
+                is_bindolence: Set(is_bindolences[i]),
+                penny_started_recording: Set(started_recordings[i]),
+                penny_recorded_full: Set(recorded_fulls[i]),
+                auction_end: Set(end_times[i]),
+            })
+        })
+        .collect()
+}
+
+fn bids_from_df(bdf: &DataFrame) -> Result<Vec<bid::ActiveModel>, ProcessorError> {
+    let auction_ids = values::<Int32Type>(bdf, "auction_id")?;
+    let prices = values::<UInt64Type>(bdf, "price")?;
+    let timestamps = datetimes(bdf, "timestamp")?;
+    let usernames = strings(bdf, "username")?;
+
+    (0..bdf.height())
+        .map(|i| {
+            Ok(bid::ActiveModel {
+                timestamp: Set(required(timestamps[i], "timestamp", i)?),
+                username: Set(required(usernames[i].clone(), "username", i)?),
+                price: Set(penny_to_dollar(required(prices[i], "price", i)?)),
+                auction_id: Set(required(auction_ids[i], "auction_id", i)?),
+            })
+        })
+        .collect()
+}
+
+async fn import_aucs(db: &DbConn, adf: &DataFrame) -> Result<(), ProcessorError> {
+    let aucs = aucs_from_df(adf)?;
+    let total = aucs.len();
+
+    let mut inserted = 0;
+    for (batch, chunk) in aucs.chunks(IMPORT_BATCH_SIZE).enumerate() {
+        inserted += Auction::insert_many(chunk.to_vec())
+            .on_conflict(
+                OnConflict::column(auction::Column::Id)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+        debug!(
+            "Imported {}/{} aucs",
+            batch * IMPORT_BATCH_SIZE + chunk.len(),
+            total
+        );
+    }
+
+    debug!(
+        "Inserted {} new aucs, {} were already present",
+        inserted,
+        total as u64 - inserted
+    );
+    Ok(())
+}
+
+async fn import_bids(db: &DbConn, bdf: &DataFrame) -> Result<(), ProcessorError> {
+    let bids = bids_from_df(bdf)?;
+    let total = bids.len();
+
+    let mut inserted = 0;
+    for (batch, chunk) in bids.chunks(IMPORT_BATCH_SIZE).enumerate() {
+        inserted += Bid::insert_many(chunk.to_vec())
+            .on_conflict(
+                OnConflict::columns([bid::Column::AuctionId, bid::Column::Price])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+        debug!(
+            "Imported {}/{} bids",
+            batch * IMPORT_BATCH_SIZE + chunk.len(),
+            total
+        );
+    }
+
+    debug!(
+        "Inserted {} new bids, {} were already present",
+        inserted,
+        total as u64 - inserted
+    );
+    Ok(())
+}
@@ -1,62 +1,210 @@
 use chrono::NaiveDateTime;
-use entity::auction::Entity as Auction;
+use entity::{auction, auction::Entity as Auction};
 use entity::{bid, bid::Entity as Bid};
-use figment::{
-    providers::{Format, Toml},
-    Figment,
-};
 use migration::{Migrator, MigratorTrait};
 use polars::df;
 use polars::prelude::*;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use sea_orm::{ColumnTrait, DbConn, EntityTrait, QueryFilter};
-use std::env;
+use sea_orm::{
+    ColumnTrait, Condition, DbConn, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+use std::path::{Path, PathBuf};
 use tracing::debug;
 
-use crate::setup;
+use crate::cli::ExportArgs;
+use crate::config::PipelineConfig;
+use crate::error::ProcessorError;
+use crate::{files, import, ops, setup, tz};
 
-pub async fn load_data(raw_adf: &str, raw_bdf: &str) {
+/// Export the database into the raw snapshot parts in `raw_dir`.
+///
+/// When a snapshot already exists only auctions and bids newer than it are fetched, and they are
+/// written as a new increment next to the existing parts. `--full` replaces the snapshot instead.
+pub async fn load_data(
+    db_full_url: &str,
+    raw_dir: &Path,
+    config: &PipelineConfig,
+    args: &ExportArgs,
+) -> Result<(), ProcessorError> {
     // Set up DB
-    let db_full_url = match env::var("DB_URL") {
-        Ok(v) => v,
-        // If env var is not set, get the url from the Rocket.toml file.
-        Err(_e) => Figment::from(Toml::file("Penny.toml"))
-            .extract_inner::<String>("default.databases.penny.url")
-            .unwrap(),
-    };
+    let db = setup::set_up_db(db_full_url, args.recreate).await?;
+
+    // Apply Database Migrations
+    Migrator::up(&db, None).await?;
+
+    debug!("Connected to database");
+
+    let adf_parts = files::parquet_parts(raw_dir, "adf")?;
+    let bdf_parts = files::parquet_parts(raw_dir, "bdf")?;
+
+    if args.full || !adf_parts[0].exists() || !bdf_parts[0].exists() {
+        // Stale increments would be read back on top of the fresh base snapshot
+        for part in adf_parts[1..].iter().chain(&bdf_parts[1..]) {
+            debug!("Removing {}", part.display());
+            std::fs::remove_file(part).map_err(|source| ProcessorError::Io {
+                path: part.clone(),
+                source,
+            })?;
+        }
+
+        load_aucs(&db, &adf_parts[0], None, args.all_columns, config).await?;
+        load_bids(&db, &bdf_parts[0], None, config).await?;
+    } else {
+        // Increments are read back together with the base snapshot, so their columns must match
+        let snapshot_schema = files::read_parquet_schema(&adf_parts[0])?;
+        if snapshot_schema.contains("category_id") != args.all_columns {
+            return Err(ProcessorError::Snapshot {
+                path: adf_parts[0].clone(),
+                reason: format!(
+                    "it was exported {} --all-columns, re-export it with --full to change columns",
+                    if args.all_columns { "without" } else { "with" },
+                ),
+            });
+        }
+        if let Ok(DataType::Datetime(_, None)) = snapshot_schema.try_get("start_time") {
+            return Err(ProcessorError::Snapshot {
+                path: adf_parts[0].clone(),
+                reason: "it was exported without a timezone, re-export it with --full".into(),
+            });
+        }
+
+        let from_id = auction_watermark(&adf_parts)?;
+        let since = bid_watermark(&bdf_parts, &config.source_timezone)?;
+        debug!(
+            "Exporting aucs from id {} and bids from {:?}",
+            from_id,
+            since.as_ref().map(|watermark| watermark.timestamp)
+        );
+
+        let adf_part = files::next_part(raw_dir, "adf", &adf_parts);
+        let bdf_part = files::next_part(raw_dir, "bdf", &bdf_parts);
+        load_aucs(&db, &adf_part, Some(from_id), args.all_columns, config).await?;
+        load_bids(&db, &bdf_part, since, config).await?;
+    }
+
+    Ok(())
+}
+
+/// First auction id to export again: everything after the newest exported auction, plus any auction
+/// that had not ended yet when it was exported so its end time gets picked up.
+fn auction_watermark(parts: &[PathBuf]) -> Result<i32, ProcessorError> {
+    let adf = files::read_parquet_parts(parts, Some(vec!["auction_id".into(), "end_time".into()]))?;
+    let adf = ops::adf_latest_snapshot(adf)?;
+
+    let after_newest = adf
+        .column("auction_id")?
+        .i32()?
+        .max()
+        .map_or(i32::MIN, |id| id + 1);
+    let unfinished = adf
+        .filter(&adf.column("end_time")?.is_null())?
+        .column("auction_id")?
+        .i32()?
+        .min();
 
-    let name = db_full_url.split('/').last().unwrap();
-    let url = db_full_url.trim_end_matches(&format!("/{}", name));
+    Ok(unfinished.map_or(after_newest, |id| id.min(after_newest)))
+}
+
+/// The newest exported bids: their timestamp, as a wall clock time in the database's source
+/// timezone, and the `(auction_id, price)` keys of every bid exported at exactly that time.
+struct BidWatermark {
+    timestamp: NaiveDateTime,
+    keys: Vec<(i32, Decimal)>,
+}
+
+impl BidWatermark {
+    /// Bids placed after the watermark, and bids placed at the watermark that were not exported
+    /// yet because they arrived after the previous export.
+    fn unexported(&self) -> Condition {
+        let new_at_watermark =
+            self.keys
+                .iter()
+                .fold(Condition::all(), |cond, &(auction_id, price)| {
+                    cond.add(
+                        Condition::any()
+                            .add(bid::Column::AuctionId.ne(auction_id))
+                            .add(bid::Column::Price.ne(price)),
+                    )
+                });
 
-    let db = match setup::set_up_db(url, name).await {
-        Ok(db) => db,
-        Err(err) => panic!("{}", err),
+        Condition::any()
+            .add(bid::Column::Timestamp.gt(self.timestamp))
+            .add(
+                Condition::all()
+                    .add(bid::Column::Timestamp.eq(self.timestamp))
+                    .add(new_at_watermark),
+            )
+    }
+}
+
+fn bid_watermark(parts: &[PathBuf], source: &str) -> Result<Option<BidWatermark>, ProcessorError> {
+    let columns = ["auction_id", "price", "timestamp"]
+        .map(String::from)
+        .to_vec();
+    let bdf = files::read_parquet_parts(parts, Some(columns))?;
+    let bdf = tz::frame_to_local(bdf, &["timestamp"], source)?;
+    let timestamps = bdf.column("timestamp")?.datetime()?;
+
+    let Some(newest) = timestamps.max() else {
+        return Ok(None);
+    };
+    let Some(timestamp) = timestamp_to_naive(newest, timestamps.time_unit()) else {
+        return Ok(None);
     };
 
-    // Apply Database Migrations, panic on error
-    Migrator::up(&db, None).await.unwrap();
+    let newest_bids = bdf.filter(&timestamps.equal(newest))?;
+    let keys = newest_bids
+        .column("auction_id")?
+        .i32()?
+        .into_no_null_iter()
+        .zip(newest_bids.column("price")?.u64()?.into_no_null_iter())
+        .map(|(auction_id, price)| (auction_id, import::penny_to_dollar(price)))
+        .collect();
 
-    debug!("Connected to database");
+    Ok(Some(BidWatermark { timestamp, keys }))
+}
 
-    load_aucs(&db, raw_adf).await.unwrap();
-    load_bids(&db, raw_bdf).await.unwrap();
+/// Convert a polars datetime value stored in `unit` back into a chrono datetime.
+pub(crate) fn timestamp_to_naive(t: i64, unit: TimeUnit) -> Option<NaiveDateTime> {
+    match unit {
+        TimeUnit::Nanoseconds => NaiveDateTime::from_timestamp_opt(
+            t.div_euclid(1_000_000_000),
+            t.rem_euclid(1_000_000_000) as u32,
+        ),
+        TimeUnit::Microseconds => NaiveDateTime::from_timestamp_micros(t),
+        TimeUnit::Milliseconds => NaiveDateTime::from_timestamp_millis(t),
+    }
 }
 
-fn dollar_to_penny(d: Decimal) -> u64 {
-    let pennies = d * dec!(100);
-    let i: i64 = pennies.round().try_into().expect("Too big.");
-    i as u64
+fn dollar_to_penny(d: Decimal) -> Result<u64, ProcessorError> {
+    let pennies = (d * dec!(100)).round();
+    let i: i64 = pennies
+        .try_into()
+        .map_err(|_| ProcessorError::PennyOverflow(d))?;
+    Ok(i as u64)
 }
 
-async fn load_aucs(db: &DbConn, path: &str) -> Result<(), PolarsError> {
-    debug!("Loading aucs from database");
-    let aucs = Auction::find().all(db).await.unwrap();
-    let mut adf = df! [
+/// Rows fetched from the database per page. Each page becomes one parquet row group.
+const EXPORT_BATCH_SIZE: u64 = 100_000;
+
+fn dollar_to_penny_opt(d: Option<Decimal>) -> Result<Option<u64>, ProcessorError> {
+    d.map(dollar_to_penny).transpose()
+}
+
+/// Auction columns used by the model. With `all_columns` every other column of the auction table
+/// is appended, decimal amounts converted to pennies and times from `source` to UTC.
+fn aucs_to_df(
+    aucs: &[auction::Model],
+    all_columns: bool,
+    source: &str,
+) -> Result<DataFrame, ProcessorError> {
+    let adf = df! [
       "auction_id"      => aucs.iter().map(|a| a.id).collect::<Vec<i32>>(),
       "start_time"      => aucs.iter().map(|a| a.start_time).collect::<Vec<NaiveDateTime>>(),
-      "bin_price"       => aucs.iter().map(|a| dollar_to_penny(a.buy_it_now_price)).collect::<Vec<u64>>(),
-      "no_jumper_limit" => aucs.iter().map(|a| dollar_to_penny(a.no_jumper_limit)).collect::<Vec<u64>>(),
+      "bin_price"       => aucs.iter().map(|a| dollar_to_penny(a.buy_it_now_price)).collect::<Result<Vec<u64>, _>>()?,
+      "no_jumper_limit" => aucs.iter().map(|a| dollar_to_penny(a.no_jumper_limit)).collect::<Result<Vec<u64>, _>>()?,
       "exchangeable"    => aucs.iter().map(|a| a.exchangeable).collect::<Vec<bool>>(),
       "one_per_user"    => aucs.iter().map(|a| a.one_per_user).collect::<Vec<bool>>(),
       "no_re_entry"     => aucs.iter().map(|a| a.no_re_entry).collect::<Vec<bool>>(),
@@ -65,35 +213,179 @@ async fn load_aucs(db: &DbConn, path: &str) -> Result<(), PolarsError> {
       "end_time"        => aucs.iter().map(|a| a.auction_end).collect::<Vec<Option<NaiveDateTime>>>(),
     ]?;
 
-    debug!("Saving aucs to file");
-    let mut new_adf_file = std::fs::File::create(path).unwrap();
-    ParquetWriter::new(&mut new_adf_file)
-        .finish(&mut adf)
-        .unwrap();
-    Ok(())
+    let adf = tz::frame_to_utc(adf, &["start_time", "end_time"], source)?;
+    if !all_columns {
+        return Ok(adf);
+    }
+
+    let details = df! [
+      "category_id"                 => aucs.iter().map(|a| a.category_id).collect::<Vec<i32>>(),
+      "category_name"               => aucs.iter().map(|a| a.category_name.as_str()).collect::<Vec<&str>>(),
+      "name"                        => aucs.iter().map(|a| a.name.as_str()).collect::<Vec<&str>>(),
+      "bin_old_price"               => aucs.iter().map(|a| dollar_to_penny(a.buy_it_now_old_price)).collect::<Result<Vec<u64>, _>>()?,
+      "product_id"                  => aucs.iter().map(|a| a.product_id).collect::<Vec<i32>>(),
+      "is_dummy_auction_for_search" => aucs.iter().map(|a| a.is_dummy_auction_for_search).collect::<Vec<bool>>(),
+      "estimated_total_cost"        => aucs.iter().map(|a| dollar_to_penny_opt(a.estimated_total_cost)).collect::<Result<Vec<Option<u64>>, _>>()?,
+      "bids_placed_by_winner"       => aucs.iter().map(|a| a.bids_placed_by_winner.as_deref()).collect::<Vec<Option<&str>>>(),
+      "price_after_promo_discount"  => aucs.iter().map(|a| dollar_to_penny_opt(a.price_after_promo_discount)).collect::<Result<Vec<Option<u64>>, _>>()?,
+      "percent_off_promo"           => aucs.iter().map(|a| a.percent_off_promo.as_deref()).collect::<Vec<Option<&str>>>(),
+      "penny_started_recording"     => aucs.iter().map(|a| a.penny_started_recording).collect::<Vec<Option<bool>>>(),
+      "penny_recorded_full"         => aucs.iter().map(|a| a.penny_recorded_full).collect::<Vec<Option<bool>>>(),
+    ]?;
+
+    Ok(adf.hstack(details.get_columns())?)
 }
 
-async fn load_bids(db: &DbConn, path: &str) -> Result<(), PolarsError> {
-    debug!("Loading bids from database");
-    let bids =
-        Bid::find()
-            .filter(bid::Column::Timestamp.gt(
-                NaiveDateTime::parse_from_str("2023-01-01 07:00 AM", "%Y-%m-%d %H:%M %p").unwrap(),
-            ))
-            .all(db)
-            .await
-            .unwrap();
-    let mut bdf = df! [
+fn bids_to_df(bids: Vec<bid::Model>, source: &str) -> Result<DataFrame, ProcessorError> {
+    let bdf = df! [
       "auction_id" => bids.iter().map(|b| b.auction_id).collect::<Vec<i32>>(),
-      "price"      => bids.iter().map(|b| dollar_to_penny(b.price)).collect::<Vec<u64>>(),
+      "price"      => bids.iter().map(|b| dollar_to_penny(b.price)).collect::<Result<Vec<u64>, _>>()?,
       "timestamp"  => bids.iter().map(|b| b.timestamp).collect::<Vec<NaiveDateTime>>(),
       "username"   => bids.into_iter().map(|b| b.username).collect::<Vec<String>>(),
     ]?;
 
-    debug!("Saving bids to file");
-    let mut new_bdf_file = std::fs::File::create(path).unwrap();
-    ParquetWriter::new(&mut new_bdf_file)
-        .finish(&mut bdf)
-        .unwrap();
+    Ok(tz::frame_to_utc(bdf, &["timestamp"], source)?)
+}
+
+/// Export auctions into `path`. With `from_id` set only auctions from that id on are exported,
+/// and nothing is written when there are none.
+async fn load_aucs(
+    db: &DbConn,
+    path: &Path,
+    from_id: Option<i32>,
+    all_columns: bool,
+    config: &PipelineConfig,
+) -> Result<(), ProcessorError> {
+    debug!("Loading aucs from database");
+    let mut select = Auction::find();
+    if let Some(from_id) = from_id {
+        select = select.filter(auction::Column::Id.gte(from_id));
+    }
+    let total = select.clone().count(db).await?;
+
+    if total == 0 && from_id.is_some() {
+        debug!("No new aucs to save");
+        return Ok(());
+    }
+
+    let source = &config.source_timezone;
+    let mut writer =
+        files::BatchWriter::new(path, &aucs_to_df(&[], all_columns, source)?.schema())?;
+    // Keyset pagination, each page starts right after the last id of the previous one
+    let mut last_id = None;
+    loop {
+        let mut page = select.clone();
+        if let Some(last_id) = last_id {
+            page = page.filter(auction::Column::Id.gt(last_id));
+        }
+        let aucs = page
+            .order_by_asc(auction::Column::Id)
+            .limit(EXPORT_BATCH_SIZE)
+            .all(db)
+            .await?;
+        let Some(last) = aucs.last() else {
+            break;
+        };
+        last_id = Some(last.id);
+
+        writer.write(&aucs_to_df(&aucs, all_columns, source)?)?;
+        debug!("Saved {}/{} aucs to file", writer.rows(), total);
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Export bids placed after the `bids_since` cutoff into `path`. With a `watermark` set only bids
+/// that are not exported yet are, and nothing is written when there are none. Both are wall clock
+/// times in the database's source timezone.
+async fn load_bids(
+    db: &DbConn,
+    path: &Path,
+    watermark: Option<BidWatermark>,
+    config: &PipelineConfig,
+) -> Result<(), ProcessorError> {
+    debug!("Loading bids from database");
+    let cutoff = config.bids_since;
+    let filter = match &watermark {
+        Some(watermark) if watermark.timestamp > cutoff => watermark.unexported(),
+        _ => Condition::all().add(bid::Column::Timestamp.gt(cutoff)),
+    };
+    let select = Bid::find().filter(filter);
+    let total = select.clone().count(db).await?;
+
+    if total == 0 && watermark.is_some() {
+        debug!("No new bids to save");
+        return Ok(());
+    }
+
+    let source = &config.source_timezone;
+    let mut writer = files::BatchWriter::new(path, &bids_to_df(Vec::new(), source)?.schema())?;
+    // Keyset pagination on the (auction_id, price) primary key
+    let mut last_key = None;
+    loop {
+        let mut page = select.clone();
+        if let Some((auction_id, price)) = last_key {
+            page = page.filter(
+                Condition::any()
+                    .add(bid::Column::AuctionId.gt(auction_id))
+                    .add(
+                        Condition::all()
+                            .add(bid::Column::AuctionId.eq(auction_id))
+                            .add(bid::Column::Price.gt(price)),
+                    ),
+            );
+        }
+        let bids = page
+            .order_by_asc(bid::Column::AuctionId)
+            .order_by_asc(bid::Column::Price)
+            .limit(EXPORT_BATCH_SIZE)
+            .all(db)
+            .await?;
+        let Some(last) = bids.last() else {
+            break;
+        };
+        last_key = Some((last.auction_id, last.price));
+
+        writer.write(&bids_to_df(bids, source)?)?;
+        debug!("Saved {}/{} bids to file", writer.rows(), total);
+    }
+    writer.finish()?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn bid_watermark_keeps_every_bid_at_the_newest_time() {
+        let dir = std::env::temp_dir().join(format!("processor-watermark-{}", std::process::id()));
+        files::create_dir(&dir).unwrap();
+        let path = dir.join("bdf.parquet");
+
+        let at = |second| {
+            NaiveDate::from_ymd_opt(2023, 1, 1)
+                .and_then(|d| d.and_hms_opt(12, 0, second))
+                .unwrap()
+        };
+        let bdf = df! [
+            "auction_id" => [1, 2, 2, 3],
+            "price"      => [5u64, 7, 8, 1],
+            "timestamp"  => [at(0), at(5), at(5), at(1)],
+            "username"   => ["a", "b", "c", "a"],
+        ]
+        .unwrap();
+        let mut bdf = tz::frame_to_utc(bdf, &["timestamp"], tz::UTC).unwrap();
+        files::write_parquet(&path, &mut bdf).unwrap();
+
+        let watermark = bid_watermark(&[path], tz::UTC).unwrap().unwrap();
+        assert_eq!(watermark.timestamp, at(5));
+        assert_eq!(watermark.keys, [(2, dec!(0.07)), (2, dec!(0.08))]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,26 @@
+use clap::Parser;
 use polars::prelude::*;
-use std::fs::File;
-use tracing::debug;
+use std::path::Path;
+use std::process::ExitCode;
+use tracing::{debug, error, info};
 
+use cli::{Cli, ExportArgs, Paths, Stage};
+use config::{OutputFormat, PipelineConfig, SequenceMetadata};
+use error::ProcessorError;
+
+mod cli;
+mod config;
+mod error;
+mod files;
+mod import;
 mod load;
 mod ops;
+mod schema;
 mod setup;
+mod tz;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Set up logging
     let (non_blocking, _guard) = tracing_appender::non_blocking(std::io::stdout());
     let subscriber = tracing_subscriber::fmt()
@@ -18,101 +31,327 @@ async fn main() {
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let raw_adf = "./data/raw/adf.parquet";
-    let raw_bdf = "./data/raw/bdf.parquet";
+    let cli = Cli::parse();
+    let paths = Paths::from(&cli);
+
+    let config = match PipelineConfig::load(&cli.config) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Could not load {}: {}", cli.config.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for stage in cli.command.stages() {
+        debug!("Running {:?} stage", stage);
+        let result = match stage {
+            Stage::Import => import_data(&paths, &cli.config, &config).await,
+            Stage::Export => {
+                export_data(&paths, &cli.config, &config, &cli.command.export_args()).await
+            }
+            Stage::Quality => quality_data(&paths, &config),
+            Stage::Process => process_data(&paths, &config).await,
+            Stage::Split => split_data(&paths, &config),
+        };
+
+        if let Err(err) = result {
+            error!("{:?} stage failed: {}", stage, err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
 
-    // Uncomment if your data is in a postgres database.
-    // load::load_data(raw_adf, raw_bdf).await;
-    process_data(raw_adf, raw_bdf).await.unwrap();
+async fn import_data(
+    paths: &Paths,
+    config_path: &Path,
+    config: &PipelineConfig,
+) -> Result<(), ProcessorError> {
+    let db_url = config::database_url(config_path)?;
+    import::import_data(&db_url, &paths.raw_dir, &config.source_timezone).await
 }
 
-async fn process_data(raw_adf_path: &str, raw_bdf_path: &str) -> Result<(), PolarsError> {
+async fn export_data(
+    paths: &Paths,
+    config_path: &Path,
+    config: &PipelineConfig,
+    args: &ExportArgs,
+) -> Result<(), ProcessorError> {
+    files::create_dir(&paths.raw_dir)?;
+    let db_url = config::database_url(config_path)?;
+    load::load_data(&db_url, &paths.raw_dir, config, args).await
+}
+
+/// Read and validate the latest raw auction and bid snapshots.
+fn load_raw(
+    paths: &Paths,
+    config: &PipelineConfig,
+) -> Result<(DataFrame, DataFrame), ProcessorError> {
     debug!("Loading auctions from file");
-    let adf_file = File::open(raw_adf_path).expect("could not open file");
-    let adf = ParquetReader::new(adf_file).finish().unwrap();
+    let adf_parts = files::parquet_parts(&paths.raw_dir, "adf")?;
+    let adf = schema::validate(
+        files::read_parquet_parts(&adf_parts, None)?,
+        &adf_parts[0].display().to_string(),
+        &schema::auction_schema(),
+        &schema::auction_detail_schema(),
+        config.coerce_types,
+    )?;
+    let adf = ops::adf_latest_snapshot(adf)?;
+    let adf = tz::frame_to_utc(adf, &["start_time", "end_time"], &config.source_timezone)?;
     debug!("Loaded auctions");
 
     debug!("Loading bids from file");
-    let bdf_file = File::open(raw_bdf_path).expect("could not open file");
-    let bdf = ParquetReader::new(bdf_file).finish().unwrap();
+    let bdf_parts = files::parquet_parts(&paths.raw_dir, "bdf")?;
+    let bdf = schema::validate(
+        files::read_parquet_parts(&bdf_parts, None)?,
+        &bdf_parts[0].display().to_string(),
+        &schema::bid_schema(),
+        &Schema::new(),
+        config.coerce_types,
+    )?;
+    let bdf = ops::bdf_latest_snapshot(bdf)?;
+    let bdf = tz::frame_to_utc(bdf, &["timestamp"], &config.source_timezone)?;
     debug!("Loaded bids");
 
-    let (adf, bdf) = ops::adf_bdf_remove_incomplete_auctions(adf, bdf);
+    Ok((adf, bdf))
+}
+
+/// Flag auctions failing the quality checks, print a per-reason summary and write the flagged
+/// auction ids to quality.parquet.
+fn quality_report(
+    paths: &Paths,
+    adf: &DataFrame,
+    bdf: &DataFrame,
+) -> Result<DataFrame, ProcessorError> {
+    let mut flags = ops::adf_bdf_quality_flags(adf, bdf)?;
+    let summary = ops::quality_summary(&flags, adf.height())?;
+    info!("Data quality of {} auctions:\n{}", adf.height(), summary);
+
+    files::create_dir(&paths.data_dir)?;
+    files::write_parquet(&paths.quality(), &mut flags)?;
+    debug!("Wrote {} flagged auctions", flags.height());
 
-    let adf = ops::adf_sin_cos_start_time(adf);
+    Ok(flags)
+}
+
+fn quality_data(paths: &Paths, config: &PipelineConfig) -> Result<(), ProcessorError> {
+    let (adf, bdf) = load_raw(paths, config)?;
+    quality_report(paths, &adf, &bdf)?;
+    Ok(())
+}
+
+async fn process_data(paths: &Paths, config: &PipelineConfig) -> Result<(), ProcessorError> {
+    let (adf, bdf) = load_raw(paths, config)?;
+
+    let flags = quality_report(paths, &adf, &bdf)?;
+    let flagged = flags.height();
+    let (flags, bdf) = ops::adf_bdf_repair_gaps(&adf, bdf, flags, config.repair.max_missing_ratio)?;
+    if flags.height() < flagged {
+        info!(
+            "Repaired {} auctions with missing bids",
+            flagged - flags.height()
+        );
+    }
+    let (adf, bdf) = ops::adf_bdf_remove_incomplete_auctions(adf, bdf, &flags);
+
+    let adf = ops::adf_sin_cos_start_time(adf, config.feature_set, &config.site_timezone);
     let adf = ops::adf_handle_nulls(adf);
 
     let bdf = ops::adf_bdf_calculate_bid_deltas(&adf, bdf);
 
     // Calculate distance to user's last bid
     let bdf = ops::bdf_distance_to_prior_bid(bdf);
+    let bdf = ops::adf_bdf_user_auction_features(&adf, bdf, config.bid_cost);
 
     let bdf = ops::bdf_mark_final_bid(bdf);
 
     let bdf = ops::bdf_mark_timestamp_index(bdf);
 
     debug!("Collecting bdf");
-    let bdf = bdf.collect().unwrap();
+    let bdf = bdf.collect()?;
     debug!("Collected bdf");
 
+    let adf = adf.collect()?;
+
+    let resets = ops::calibrate_clock(&adf, &bdf, &config.clock.bands, &config.split)?;
+    let bdf = ops::bdf_clock_features(bdf, &config.clock.bands, &resets).collect()?;
+    let bdf = ops::bdf_competition_features(bdf, &config.competition)?;
+
     debug!("Collecting user stats");
-    let user_stats = ops::bdf_user_historical_stats(&bdf);
-    let mut user_stats = user_stats.collect().unwrap();
+    let user_stats = ops::bdf_user_historical_stats(&bdf, &adf, &config.user_stats)?;
+    let mut user_stats = user_stats.collect()?;
     debug!("Collected user stats");
 
     debug!("Writing user info");
-    let mut user_file = std::fs::File::create("./data/user_stats.parquet").unwrap();
-    ParquetWriter::new(&mut user_file)
-        .finish(&mut user_stats)
-        .unwrap();
+    files::write_parquet(&paths.user_stats(), &mut user_stats)?;
     debug!("Wrote user info");
 
     let bdf = ops::bdf_user_stats_join(bdf, &user_stats);
-
-    let adf = adf.collect().unwrap();
-    let bdf = bdf.collect().unwrap();
+    let bdf = bdf.collect()?;
 
     let df = ops::adf_bdf_make_df(adf, bdf);
+    let df = ops::df_bin_economics(df, config.bid_cost);
 
     debug!("Collecting df");
-    let mut df = df.collect().unwrap();
+    let mut df = df.collect()?;
     debug!("Collected df");
 
     debug!("Writing df");
-    let mut df_file = std::fs::File::create("./data/df.parquet").unwrap();
-    ParquetWriter::new(&mut df_file).finish(&mut df).unwrap();
+    files::write_parquet(&paths.df(), &mut df)?;
+    config.write(&paths.df_config())?;
     debug!("Wrote df");
 
-    let (train_df, val_df, test_df) = ops::train_test_split(&df);
-    let train_df = train_df.collect().unwrap();
-    let val_df = val_df.collect().unwrap();
-    let test_df = test_df.collect().unwrap();
+    Ok(())
+}
+
+fn split_data(paths: &Paths, config: &PipelineConfig) -> Result<(), ProcessorError> {
+    debug!("Loading df from file");
+    let df = files::read_parquet(&paths.df())?;
+    debug!("Loaded df");
+
+    let (train_df, val_df, test_df) = ops::train_test_split(&df, &config.split)?;
+    let train_df = train_df.collect()?;
+    let val_df = val_df.collect()?;
+    let test_df = test_df.collect()?;
+
+    files::create_dir(&paths.split_dir)?;
 
     debug!("Writing train/test frames");
-    write_frames(train_df, "train");
-    write_frames(val_df, "val");
-    write_frames(test_df, "test");
+    write_frames(paths, config, train_df, "train")?;
+    write_frames(paths, config, val_df, "val")?;
+    write_frames(paths, config, test_df, "test")?;
+
+    for (k, (train_df, val_df)) in ops::walk_forward_folds(&df, &config.split)?
+        .into_iter()
+        .enumerate()
+    {
+        let fold_paths = paths.fold(k);
+        files::create_dir(&fold_paths.split_dir)?;
+        write_frames(&fold_paths, config, train_df.collect()?, "train")?;
+        write_frames(&fold_paths, config, val_df.collect()?, "val")?;
+    }
+
+    if config.output.format == OutputFormat::List {
+        sequence_metadata(config).write(&paths.sequence_metadata())?;
+    }
+    config.write(&paths.split_config())?;
+    // The split config only covers the split, df.parquet was shaped by the process config
+    if paths.df_config().exists() {
+        files::copy_file(&paths.df_config(), &paths.split_df_config())?;
+    } else {
+        debug!("No process config next to df, it was built by an older processor");
+    }
     debug!("Wrote train/test frames");
 
     Ok(())
 }
 
-fn write_frames(df: DataFrame, id: &str) {
-    let tdf = ops::time_series_data(&df);
-    let mut tdf = tdf.collect().unwrap();
+fn write_frames(
+    paths: &Paths,
+    config: &PipelineConfig,
+    df: DataFrame,
+    id: &str,
+) -> Result<(), ProcessorError> {
+    if config.output.format == OutputFormat::List {
+        let sdf = ops::sequence_data(
+            &df,
+            &ops::time_series_features(&config.user_stats.windows),
+            config.feature_set,
+            config.output.max_len,
+        );
+        let mut sdf = sdf.collect()?;
+        files::write_parquet(&paths.split_file("seq", id), &mut sdf)?;
+        return Ok(());
+    }
 
-    let mut tdf_file = std::fs::File::create(format!("./data/split/tdf_{id}.parquet")).unwrap();
-    ParquetWriter::new(&mut tdf_file).finish(&mut tdf).unwrap();
+    let features = ops::time_series_features(&config.user_stats.windows);
+    let tdf = ops::time_series_data(&df, &features, config.lookback);
+    let mut tdf = tdf.collect()?;
+    files::write_parquet(&paths.split_file("tdf", id), &mut tdf)?;
 
-    let mdf = ops::meta_data(&df);
-    let mut mdf = mdf.collect().unwrap();
-
-    let mut mdf_file = std::fs::File::create(format!("./data/split/mdf_{id}.parquet")).unwrap();
-    ParquetWriter::new(&mut mdf_file).finish(&mut mdf).unwrap();
+    let mdf = ops::meta_data(&df, config.feature_set);
+    let mut mdf = mdf.collect()?;
+    files::write_parquet(&paths.split_file("mdf", id), &mut mdf)?;
 
     let y = ops::y_data(&df);
-    let mut y = y.collect().unwrap();
+    let mut y = y.collect()?;
+    files::write_parquet(&paths.split_file("y", id), &mut y)?;
+
+    verify_alignment(paths, id)
+}
+
+/// Read the row keys back from the written `tdf`, `mdf` and `y` files and check they match row by
+/// row, since the trainer lines them up by position.
+fn verify_alignment(paths: &Paths, id: &str) -> Result<(), ProcessorError> {
+    let key = || Some(ops::ROW_KEY.map(String::from).to_vec());
+
+    let expected = paths.split_file("tdf", id);
+    let tdf_keys = files::read_parquet_columns(&expected, key())?;
+    for kind in ["mdf", "y"] {
+        let actual = paths.split_file(kind, id);
+        if !files::read_parquet_columns(&actual, key())?.frame_equal_missing(&tdf_keys) {
+            return Err(ProcessorError::Misaligned { expected, actual });
+        }
+    }
+
+    debug!("Verified {} split files are aligned", id);
+    Ok(())
+}
+
+fn sequence_metadata(config: &PipelineConfig) -> SequenceMetadata {
+    let names = |columns: &[&str]| -> Vec<String> { columns.iter().map(|&c| c.into()).collect() };
+    SequenceMetadata {
+        max_len: config.output.max_len,
+        length_column: "seq_len".into(),
+        features: ops::time_series_features(&config.user_stats.windows),
+        meta: names(&ops::meta_features(config.feature_set)),
+        labels: names(&["final_bid"]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_split_files_are_aligned() {
+        let dir = std::env::temp_dir().join(format!("processor-split-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = Paths {
+            raw_dir: dir.clone(),
+            data_dir: dir.clone(),
+            split_dir: dir.clone(),
+        };
+        let config = PipelineConfig::default();
+
+        // Two auctions with their bids interleaved and out of price order
+        let auction_ids = [2, 1, 2, 1, 1];
+        let prices = [2u64, 3, 1, 1, 2];
+        let mut columns = vec![
+            Series::new("auction_id", auction_ids),
+            Series::new("price", prices),
+            Series::new("final_bid", [true, true, false, false, false]),
+        ];
+        let features = ops::time_series_features(&config.user_stats.windows);
+        let meta = ops::meta_features(config.feature_set);
+        for name in features.iter().map(String::as_str).chain(meta) {
+            if !ops::ROW_KEY.contains(&name) {
+                columns.push(Series::new(name, prices.map(|price| price as f64)));
+            }
+        }
+        let df = DataFrame::new(columns).unwrap();
+
+        write_frames(&paths, &config, df, "train").unwrap();
+
+        let mdf = files::read_parquet_columns(&paths.split_file("mdf", "train"), None).unwrap();
+        assert_eq!(&mdf.get_column_names()[..2], ops::ROW_KEY);
+        let prices = mdf.column("price").unwrap().u64().unwrap();
+        assert_eq!(
+            prices.into_no_null_iter().collect::<Vec<_>>(),
+            [1, 2, 3, 1, 2]
+        );
 
-    let mut y_file = std::fs::File::create(format!("./data/split/y_{id}.parquet")).unwrap();
-    ParquetWriter::new(&mut y_file).finish(&mut y).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
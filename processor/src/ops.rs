@@ -1,9 +1,29 @@
+use std::collections::HashMap;
 use std::f64::consts::PI;
 
 use polars::lazy::dsl::col;
 use polars::prelude::*;
 use tracing::debug;
 
+use crate::config::{
+    ClockBand, CompetitionConfig, FeatureSet, FoldWindow, SplitConfig, UserStatsConfig,
+};
+use crate::tz;
+
+/// Keep the most recent copy of each auction when snapshot parts overlap.
+pub fn adf_latest_snapshot(adf: DataFrame) -> PolarsResult<DataFrame> {
+    adf.unique_stable(Some(&["auction_id".into()]), UniqueKeepStrategy::Last, None)
+}
+
+/// Keep the most recent copy of each bid when snapshot parts overlap.
+pub fn bdf_latest_snapshot(bdf: DataFrame) -> PolarsResult<DataFrame> {
+    bdf.unique_stable(
+        Some(&["auction_id".into(), "price".into()]),
+        UniqueKeepStrategy::Last,
+        None,
+    )
+}
+
 pub fn adf_handle_nulls(adf: LazyFrame) -> LazyFrame {
     adf.with_columns([
         col("is_bindolence").fill_null(false).alias("is_bindolence"),
@@ -11,87 +31,312 @@ pub fn adf_handle_nulls(adf: LazyFrame) -> LazyFrame {
     ])
 }
 
-pub fn adf_sin_cos_start_time(adf: LazyFrame) -> LazyFrame {
-    adf.with_columns([
-        col("start_time").dt().hour().alias("start_hour"),
-        col("start_time").dt().minute().alias("start_minute"),
-    ])
-    .with_columns([
-        col("start_hour")
-            .cast(DataType::Float64)
-            .map(
-                |x| Ok(Some(x / 23. * PI * 2.)),
-                GetOutput::from_type(DataType::Float64),
-            )
-            .sin()
-            .alias("start_hour_sin"),
-        col("start_hour")
-            .cast(DataType::Float64)
-            .map(
-                |x| Ok(Some(x / 23. * PI * 2.)),
-                GetOutput::from_type(DataType::Float64),
-            )
-            .cos()
-            .alias("start_hour_cos"),
-        col("start_minute")
-            .cast(DataType::Float64)
-            .map(
-                |x| Ok(Some(x / 23. * PI * 2.)),
-                GetOutput::from_type(DataType::Float64),
-            )
-            .sin()
-            .alias("start_minute_sin"),
-        col("start_minute")
-            .cast(DataType::Float64)
-            .map(
-                |x| Ok(Some(x / 23. * PI * 2.)),
-                GetOutput::from_type(DataType::Float64),
-            )
-            .cos()
-            .alias("start_minute_cos"),
-    ])
-    .drop_columns(["start_hour", "start_minute"])
+/// Encode a periodic `value` as the point on the unit circle it maps to, so the end of a cycle
+/// sits next to its start. Returns the `{name}_sin` and `{name}_cos` columns.
+pub fn cyclic_encode(value: Expr, period: f64, name: &str) -> [Expr; 2] {
+    let angle = value.cast(DataType::Float64) * lit(2. * PI / period);
+    [
+        angle.clone().sin().alias(&format!("{name}_sin")),
+        angle.cos().alias(&format!("{name}_cos")),
+    ]
 }
 
+/// Columns [`adf_sin_cos_start_time`] adds for `feature_set`.
+pub fn start_time_features(feature_set: FeatureSet) -> Vec<&'static str> {
+    match feature_set {
+        FeatureSet::V1 => vec![
+            "start_hour_sin",
+            "start_hour_cos",
+            "start_minute_sin",
+            "start_minute_cos",
+        ],
+        FeatureSet::V2 => vec![
+            "start_hour_sin",
+            "start_hour_cos",
+            "start_minute_sin",
+            "start_minute_cos",
+            "start_weekday_sin",
+            "start_weekday_cos",
+            "start_time_of_day_sin",
+            "start_time_of_day_cos",
+        ],
+    }
+}
+
+/// Encode the auction start as seen on the wall clock of the `site` timezone.
+pub fn adf_sin_cos_start_time(adf: LazyFrame, feature_set: FeatureSet, site: &str) -> LazyFrame {
+    let start_time = tz::local(col("start_time"), site);
+    let hour = start_time.clone().dt().hour();
+    let minute = start_time.clone().dt().minute();
+
+    let features = match feature_set {
+        // Kept for models trained before the fix, hours and minutes both wrap every 23 units
+        FeatureSet::V1 => [
+            cyclic_encode(hour, 23., "start_hour"),
+            cyclic_encode(minute, 23., "start_minute"),
+        ]
+        .concat(),
+        FeatureSet::V2 => [
+            cyclic_encode(hour.clone(), 24., "start_hour"),
+            cyclic_encode(minute.clone(), 60., "start_minute"),
+            cyclic_encode(start_time.dt().weekday(), 7., "start_weekday"),
+            cyclic_encode(
+                hour.cast(DataType::Float64) * lit(60.) + minute.cast(DataType::Float64),
+                24. * 60.,
+                "start_time_of_day",
+            ),
+        ]
+        .concat(),
+    };
+
+    adf.with_columns(features)
+}
+
+/// Reasons an auction is dropped from the dataset, one boolean column each in the quality flags.
+pub const QUALITY_CHECKS: [&str; 6] = [
+    "missing_bids",
+    "price_gaps",
+    "duplicate_prices",
+    "missing_end_time",
+    "bids_after_end",
+    "bids_before_start",
+];
+
+/// Flag every auction that fails at least one of the [`QUALITY_CHECKS`].
+pub fn adf_bdf_quality_flags(adf: &DataFrame, bdf: &DataFrame) -> PolarsResult<DataFrame> {
+    let bid_stats = bdf.clone().lazy().groupby([col("auction_id")]).agg([
+        col("price").count().alias("entries"),
+        col("price").n_unique().alias("unique_prices"),
+        col("price").max().alias("max_price"),
+        col("timestamp").min().alias("first_bid"),
+        col("timestamp").max().alias("last_bid"),
+    ]);
+
+    adf.clone()
+        .lazy()
+        .select([col("auction_id"), col("start_time"), col("end_time")])
+        .join(
+            bid_stats,
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .select([
+            col("auction_id"),
+            col("entries").is_null().alias("missing_bids"),
+            col("unique_prices")
+                .lt(col("max_price"))
+                .fill_null(false)
+                .alias("price_gaps"),
+            col("entries")
+                .gt(col("unique_prices"))
+                .fill_null(false)
+                .alias("duplicate_prices"),
+            col("end_time").is_null().alias("missing_end_time"),
+            col("last_bid")
+                .gt(col("end_time"))
+                .fill_null(false)
+                .alias("bids_after_end"),
+            col("first_bid")
+                .lt(col("start_time"))
+                .fill_null(false)
+                .alias("bids_before_start"),
+        ])
+        .filter(
+            QUALITY_CHECKS
+                .iter()
+                .fold(lit(false), |any, check| any.or(col(check))),
+        )
+        .sort("auction_id", Default::default())
+        .collect()
+}
+
+/// Number and share of auctions failing each check, plus the total dropped.
+pub fn quality_summary(flags: &DataFrame, auctions: usize) -> PolarsResult<DataFrame> {
+    let mut reasons = Vec::new();
+    let mut counts = Vec::new();
+    for check in QUALITY_CHECKS {
+        reasons.push(check);
+        counts.push(flags.column(check)?.bool()?.sum().unwrap_or(0) as u64);
+    }
+    reasons.push("dropped");
+    counts.push(flags.height() as u64);
+
+    let shares = counts
+        .iter()
+        .map(|&count| count as f64 / auctions.max(1) as f64)
+        .collect::<Vec<f64>>();
+
+    df! [
+        "reason"   => reasons,
+        "auctions" => counts,
+        "share"    => shares,
+    ]
+}
+
+/// Drop the auctions in `flags`, and every bid that does not belong to a remaining auction.
 pub fn adf_bdf_remove_incomplete_auctions(
     adf: DataFrame,
     bdf: DataFrame,
+    flags: &DataFrame,
 ) -> (LazyFrame, LazyFrame) {
-    let filtered_auctions = bdf
+    let adf = adf.lazy().join(
+        flags.clone().lazy().select([col("auction_id")]),
+        [col("auction_id")],
+        [col("auction_id")],
+        JoinArgs::new(JoinType::Anti),
+    );
+
+    let bdf = bdf.lazy().join(
+        adf.clone().select([col("auction_id")]),
+        [col("auction_id")],
+        [col("auction_id")],
+        JoinArgs::new(JoinType::Semi),
+    );
+
+    (adf, bdf)
+}
+
+/// Fill the holes in the price sequence of auctions whose only problem is a few missed bids.
+///
+/// Auctions flagged for `price_gaps` alone and missing less than `max_missing_ratio` of their
+/// bids get a placeholder bid for every missing price, with a null username and a timestamp
+/// interpolated between the neighbouring bids (or the auction start). Every bid gets an
+/// `imputed` column, and the repaired auctions are removed from the returned flags.
+pub fn adf_bdf_repair_gaps(
+    adf: &DataFrame,
+    bdf: DataFrame,
+    flags: DataFrame,
+    max_missing_ratio: f64,
+) -> PolarsResult<(DataFrame, DataFrame)> {
+    let bdf = bdf.lazy().with_column(lit(false).alias("imputed"));
+    if max_missing_ratio <= 0. {
+        return Ok((flags, bdf.collect()?));
+    }
+
+    let only_gaps = QUALITY_CHECKS
+        .iter()
+        .filter(|&&check| check != "price_gaps")
+        .fold(col("price_gaps"), |only, check| only.and(col(check).not()));
+
+    let repairable = flags
         .clone()
         .lazy()
-        .groupby([col("auction_id")])
-        .agg([
-            col("price").count().alias("entries"),
-            col("price").max().alias("max_price"),
-        ])
+        .filter(only_gaps)
+        .select([col("auction_id")])
         .join(
-            adf.clone().lazy(),
+            bdf.clone().groupby([col("auction_id")]).agg([
+                col("price").n_unique().alias("unique_prices"),
+                col("price").max().alias("max_price"),
+            ]),
             [col("auction_id")],
             [col("auction_id")],
             JoinArgs::new(JoinType::Inner),
         )
         .filter(
-            col("entries")
-                .eq(col("max_price"))
-                .and(col("end_time").is_not_null()),
+            (lit(1.)
+                - col("unique_prices").cast(DataType::Float64)
+                    / col("max_price").cast(DataType::Float64))
+            .lt(lit(max_missing_ratio)),
         )
-        .select([col("auction_id")])
-        .collect()
-        .unwrap();
+        .collect()?;
+
+    // Every price from the auction start (price 0) up to the final bid
+    let mut auction_ids = Vec::new();
+    let mut prices = Vec::new();
+    let ids = repairable.column("auction_id")?.i32()?;
+    let max_prices = repairable.column("max_price")?.u64()?;
+    for (id, max_price) in ids.into_iter().zip(max_prices) {
+        if let (Some(id), Some(max_price)) = (id, max_price) {
+            auction_ids.extend(std::iter::repeat(id).take(max_price as usize + 1));
+            prices.extend(0..=max_price);
+        }
+    }
+    let grid = df! [
+        "auction_id" => auction_ids,
+        "price"      => prices,
+    ]?;
 
-    let adf = adf
+    let schema = bdf.schema()?;
+    let timestamp_type = schema.try_get("timestamp")?.clone();
+    let columns = schema
+        .iter_names()
+        .map(|name| col(name.as_str()))
+        .collect::<Vec<Expr>>();
+
+    let repaired = grid
         .lazy()
-        .inner_join(filtered_auctions.clone().lazy(), "auction_id", "auction_id");
+        .join(
+            bdf.clone().drop_columns(["imputed"]),
+            [col("auction_id"), col("price")],
+            [col("auction_id"), col("price")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .join(
+            adf.clone()
+                .lazy()
+                .select([col("auction_id"), col("start_time")]),
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .with_columns([
+            when(col("price").eq(0))
+                .then(col("start_time").cast(timestamp_type))
+                .otherwise(col("timestamp"))
+                .alias("timestamp"),
+            col("timestamp").is_null().alias("imputed"),
+        ])
+        .sort_by_exprs(
+            [col("auction_id"), col("price")],
+            [false, false],
+            false,
+            false,
+        )
+        .with_column(
+            col("timestamp")
+                .interpolate(InterpolationMethod::Linear)
+                .over(["auction_id"]),
+        )
+        .filter(col("price").gt(0))
+        .select(columns.clone());
 
-    let bdf = bdf
+    let repaired_ids = repairable.lazy().select([col("auction_id")]);
+    let bdf = concat(
+        [
+            bdf.join(
+                repaired_ids.clone(),
+                [col("auction_id")],
+                [col("auction_id")],
+                JoinArgs::new(JoinType::Anti),
+            )
+            .select(columns),
+            repaired,
+        ],
+        Default::default(),
+    )?
+    .collect()?;
+
+    let flags = flags
         .lazy()
-        .inner_join(filtered_auctions.lazy(), "auction_id", "auction_id");
+        .join(
+            repaired_ids,
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Anti),
+        )
+        .collect()?;
 
-    (adf, bdf)
+    Ok((flags, bdf))
 }
 
+/// Milliseconds since the previous bid (`delta`) and since the auction start (`time_since_start`)
+/// for every bid. The first bid's delta is measured from the auction start.
+///
+/// Both are computed from epoch milliseconds, so they do not depend on the stored time unit.
 pub fn adf_bdf_calculate_bid_deltas(adf: &LazyFrame, bdf: LazyFrame) -> LazyFrame {
+    let millis = |name: &str| col(name).dt().timestamp(TimeUnit::Milliseconds);
+
     bdf.inner_join(
         adf.clone().select([col("auction_id"), col("start_time")]),
         "auction_id",
@@ -99,19 +344,24 @@ pub fn adf_bdf_calculate_bid_deltas(adf: &LazyFrame, bdf: LazyFrame) -> LazyFram
     )
     .sort("price", Default::default())
     .with_column(
-        col("timestamp")
+        millis("timestamp")
             .shift(1)
             .over(["auction_id"])
-            .alias("prior_bid_timestamp"),
+            .alias("prior_bid_millis"),
     )
-    .with_column(
-        when(col("price").eq(1))
-            .then((col("start_time") - col("timestamp")) / lit(1000000))
-            .otherwise((col("timestamp") - col("prior_bid_timestamp")) / lit(1000000))
-            .cast(DataType::Int64)
+    .with_columns([
+        // A delta spanning a placeholder bid relies on an interpolated timestamp
+        col("imputed")
+            .or(col("imputed")
+                .shift(1)
+                .over(["auction_id"])
+                .fill_null(false))
+            .alias("delta_imputed"),
+        (millis("timestamp") - col("prior_bid_millis").fill_null(millis("start_time")))
             .alias("delta"),
-    )
-    .drop_columns(["prior_bid_timestamp", "start_time"])
+        (millis("timestamp") - millis("start_time")).alias("time_since_start"),
+    ])
+    .drop_columns(["prior_bid_millis", "start_time"])
 }
 
 pub fn bdf_distance_to_prior_bid(bdf: LazyFrame) -> LazyFrame {
@@ -124,6 +374,55 @@ pub fn bdf_distance_to_prior_bid(bdf: LazyFrame) -> LazyFrame {
     )
 }
 
+/// What each bidder has sunk into the auction so far, counting the current bid:
+///
+/// - `user_auction_bids`: bids placed
+/// - `user_auction_spend`: pennies paid for them at `bid_cost` a bid
+/// - `user_first_bid_price`: price of the bidder's first bid
+/// - `user_bids_per_minute`: bids after the first per minute since it, 0 at the first bid
+/// - `user_entry_vs_no_jumper`: first bid price as a share of `no_jumper_limit`, 0 without a limit
+///
+/// Like `prior_bid_dist`, the placeholder bids of an auction are grouped as one bidder.
+pub fn adf_bdf_user_auction_features(adf: &LazyFrame, bdf: LazyFrame, bid_cost: u64) -> LazyFrame {
+    let user = ["auction_id", "username"];
+    let millis = col("timestamp").dt().timestamp(TimeUnit::Milliseconds);
+
+    bdf.inner_join(
+        adf.clone()
+            .select([col("auction_id"), col("no_jumper_limit")]),
+        "auction_id",
+        "auction_id",
+    )
+    .sort("price", Default::default())
+    .with_columns([
+        (col("price").cumcount(false).over(user) + lit(1u32)).alias("user_auction_bids"),
+        col("price")
+            .first()
+            .over(user)
+            .alias("user_first_bid_price"),
+        (millis.clone() - millis.first().over(user)).alias("user_auction_millis"),
+    ])
+    .with_columns([
+        (col("user_auction_bids").cast(DataType::UInt64) * lit(bid_cost))
+            .alias("user_auction_spend"),
+        when(col("user_auction_millis").gt(0))
+            .then(
+                (col("user_auction_bids") - lit(1u32)).cast(DataType::Float64) * lit(60_000.)
+                    / col("user_auction_millis").cast(DataType::Float64),
+            )
+            .otherwise(lit(0.))
+            .alias("user_bids_per_minute"),
+        when(col("no_jumper_limit").gt(0))
+            .then(
+                col("user_first_bid_price").cast(DataType::Float64)
+                    / col("no_jumper_limit").cast(DataType::Float64),
+            )
+            .otherwise(lit(0.))
+            .alias("user_entry_vs_no_jumper"),
+    ])
+    .drop_columns(["user_auction_millis", "no_jumper_limit"])
+}
+
 pub fn bdf_mark_final_bid(bdf: LazyFrame) -> LazyFrame {
     bdf.with_column(
         col("price")
@@ -133,52 +432,410 @@ pub fn bdf_mark_final_bid(bdf: LazyFrame) -> LazyFrame {
     )
 }
 
+/// Index of the clock band each `price` falls in.
+fn clock_band(price: Expr, bands: &[ClockBand]) -> Expr {
+    bands.iter().skip(1).fold(lit(0u32), |band, next| {
+        band + price
+            .clone()
+            .gt_eq(lit(next.from_price))
+            .cast(DataType::UInt32)
+    })
+}
+
+/// Timer value in milliseconds after a bid in each clock band.
+///
+/// Bands without a configured `reset_to` use the median time between the final bid and the end
+/// of the auctions that ended in them, falling back to the median over all auctions. Only the
+/// auctions [`train_test_split`] puts in train are used, so val and test end times do not leak
+/// into the features. Walk-forward folds validating on the train period still see them.
+pub fn calibrate_clock(
+    adf: &DataFrame,
+    bdf: &DataFrame,
+    bands: &[ClockBand],
+    split: &SplitConfig,
+) -> PolarsResult<Vec<i64>> {
+    let df = bdf
+        .clone()
+        .lazy()
+        .select([col("auction_id"), col("timestamp")])
+        .inner_join(
+            adf.clone()
+                .lazy()
+                .select([col("auction_id"), col("start_time"), col("end_time")]),
+            col("auction_id"),
+            col("auction_id"),
+        )
+        .collect()?;
+    let train = train_auctions(&df, split)?;
+
+    let remaining = bdf
+        .clone()
+        .lazy()
+        .filter(col("final_bid"))
+        .join(
+            train,
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Semi),
+        )
+        .join(
+            adf.clone()
+                .lazy()
+                .select([col("auction_id"), col("end_time")]),
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([
+            clock_band(col("price"), bands).alias("band"),
+            (col("end_time").dt().timestamp(TimeUnit::Milliseconds)
+                - col("timestamp").dt().timestamp(TimeUnit::Milliseconds))
+            .alias("remaining"),
+        ])
+        .collect()?;
+
+    let overall = remaining.column("remaining")?.median();
+    let per_band = remaining
+        .lazy()
+        .groupby([col("band")])
+        .agg([col("remaining").median()])
+        .collect()?;
+    let bands_found = per_band.column("band")?.u32()?;
+    let medians = per_band.column("remaining")?.cast(&DataType::Float64)?;
+    let medians = medians.f64()?;
+
+    bands
+        .iter()
+        .enumerate()
+        .map(|(i, band)| {
+            if let Some(reset_to) = band.reset_to() {
+                return Ok(reset_to.num_milliseconds());
+            }
+
+            let median = bands_found
+                .into_iter()
+                .zip(medians)
+                .find_map(|(found, median)| (found == Some(i as u32)).then_some(median).flatten())
+                .or(overall);
+            match median {
+                Some(median) => {
+                    debug!(
+                        "Calibrated clock reset from price {} to {:.0}ms",
+                        band.from_price, median
+                    );
+                    Ok(median.round() as i64)
+                }
+                None => polars_bail!(
+                    ComputeError: "no ended auctions to calibrate the clock band from price {}",
+                    band.from_price
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Reconstruct the countdown timer: `clock_reset_to` is the timer value in milliseconds right after
+/// each bid, `clock_remaining_at_bid` what was left of the previous bid's timer when the bid landed.
+/// The first bid of an auction has no timer to compare against.
+pub fn bdf_clock_features(bdf: DataFrame, bands: &[ClockBand], resets: &[i64]) -> LazyFrame {
+    let reset_to =
+        bands
+            .iter()
+            .zip(resets)
+            .skip(1)
+            .fold(lit(resets[0]), |reset_to, (band, &reset)| {
+                when(col("price").gt_eq(lit(band.from_price)))
+                    .then(lit(reset))
+                    .otherwise(reset_to)
+            });
+
+    bdf.lazy()
+        .sort("price", Default::default())
+        .with_column(reset_to.alias("clock_reset_to"))
+        .with_column(
+            (col("clock_reset_to").shift(1).over(["auction_id"]) - col("delta"))
+                .alias("clock_remaining_at_bid"),
+        )
+}
+
+/// How contested each auction is at every bid, counting the bid itself:
+///
+/// - `bidders_last_bids`: distinct bidders among the latest `last_bids` bids
+/// - `bidders_last_time`: distinct bidders among the bids placed within `last`
+/// - `bid_share_hhi`: Herfindahl index of the bidders' shares of the auction's bids so far
+/// - `committed_bidders`: bidders with at least `min_bids` bids so far
+/// - `is_leader`: whether the bidder has placed the most bids so far, ties included
+///
+/// Placeholder bids have no bidder, so they only advance the windows.
+pub fn bdf_competition_features(
+    bdf: DataFrame,
+    config: &CompetitionConfig,
+) -> PolarsResult<DataFrame> {
+    let auction = ["auction_id"];
+    let is_bid = col("username").is_not_null();
+
+    let mut bdf = bdf
+        .lazy()
+        .sort_by_exprs(
+            [col("auction_id"), col("price")],
+            [false, false],
+            false,
+            false,
+        )
+        .with_column(
+            when(is_bid.clone())
+                .then(
+                    col("price")
+                        .cumcount(false)
+                        .over(["auction_id", "username"])
+                        + lit(1u32),
+                )
+                .otherwise(lit(NULL).cast(DataType::UInt32))
+                .alias("bidder_bids"),
+        )
+        .with_columns([
+            // Each bid raises the bidder's squared count from (n - 1)² to n²
+            (col("bidder_bids").cast(DataType::Int64) * lit(2i64) - lit(1i64))
+                .fill_null(lit(0i64))
+                .cumsum(false)
+                .over(auction)
+                .alias("squared_bids"),
+            is_bid
+                .cast(DataType::UInt32)
+                .cumsum(false)
+                .over(auction)
+                .alias("bids"),
+            col("bidder_bids")
+                .eq(lit(config.min_bids as u32))
+                .fill_null(false)
+                .cast(DataType::UInt32)
+                .cumsum(false)
+                .over(auction)
+                .cast(DataType::UInt32)
+                .alias("committed_bidders"),
+            col("bidder_bids")
+                .cummax(false)
+                .over(auction)
+                .alias("most_bids"),
+        ])
+        .with_columns([
+            when(col("bids").gt(0))
+                .then(
+                    col("squared_bids").cast(DataType::Float64)
+                        / col("bids").cast(DataType::Float64).pow(2),
+                )
+                .otherwise(lit(NULL).cast(DataType::Float64))
+                .alias("bid_share_hhi"),
+            col("bidder_bids").eq(col("most_bids")).alias("is_leader"),
+        ])
+        .drop_columns(["bidder_bids", "squared_bids", "bids", "most_bids"])
+        .collect()?;
+
+    let millis = bdf
+        .clone()
+        .lazy()
+        .select([col("timestamp").dt().timestamp(TimeUnit::Milliseconds)])
+        .collect()?;
+    let millis = millis.column("timestamp")?.i64()?.to_vec();
+    let last = config.last().num_milliseconds();
+
+    let auction_ids = bdf.column("auction_id")?.i32()?.to_vec();
+    let usernames = bdf
+        .column("username")?
+        .utf8()?
+        .into_iter()
+        .collect::<Vec<_>>();
+    let last_bids = sliding_bidders(&auction_ids, &usernames, |first, i| {
+        i - first >= config.last_bids
+    });
+    let last_time = sliding_bidders(
+        &auction_ids,
+        &usernames,
+        |first, i| matches!((millis[first], millis[i]), (Some(first), Some(now)) if now - first >= last),
+    );
+
+    bdf.with_column(Series::new("bidders_last_bids", last_bids))?;
+    bdf.with_column(Series::new("bidders_last_time", last_time))?;
+    Ok(bdf)
+}
+
+/// Distinct bidders in a window sliding over the bids of each auction, in bid order. Bids drop out
+/// of the window from its start for as long as `expired(first, current)` holds.
+fn sliding_bidders(
+    auction_ids: &[Option<i32>],
+    usernames: &[Option<&str>],
+    expired: impl Fn(usize, usize) -> bool,
+) -> Vec<u32> {
+    let mut counts = HashMap::new();
+    let mut first = 0;
+
+    (0..usernames.len())
+        .map(|i| {
+            if i > 0 && auction_ids[i] != auction_ids[i - 1] {
+                counts.clear();
+                first = i;
+            }
+            if let Some(user) = usernames[i] {
+                *counts.entry(user).or_insert(0u32) += 1;
+            }
+            while first < i && expired(first, i) {
+                if let Some(user) = usernames[first] {
+                    let count = counts.get_mut(user).expect("bidder in the window");
+                    *count -= 1;
+                    if *count == 0 {
+                        counts.remove(user);
+                    }
+                }
+                first += 1;
+            }
+            counts.len() as u32
+        })
+        .collect()
+}
+
 pub fn bdf_mark_timestamp_index(bdf: LazyFrame) -> LazyFrame {
     bdf.sort("timestamp", Default::default())
         .with_row_count("idx", None)
 }
 
-pub fn bdf_user_historical_stats(bdf: &DataFrame) -> LazyFrame {
-    bdf.clone()
+/// Window covering a user's whole history.
+pub const LIFETIME: &str = "lifetime";
+
+/// Statistics of a user's history, computed once per window in `user_stats.windows`.
+pub const USER_STATS: [&str; 6] = [
+    "avg_delta",
+    "std_delta",
+    "total_spend",
+    "avg_bid_price",
+    "std_bid_price",
+    "wins",
+];
+
+/// Each user's bidding history over every configured window before each of their bids, as
+/// `{stat}_{window}` columns.
+///
+/// Only what was known when the bid was placed counts: bids placed strictly before it, and wins of
+/// auctions that ended strictly before it. Placeholder bids have no bidder, so they are left out.
+/// Returns one row per user and bid timestamp.
+pub fn bdf_user_historical_stats(
+    bdf: &DataFrame,
+    adf: &DataFrame,
+    config: &UserStatsConfig,
+) -> PolarsResult<LazyFrame> {
+    let bids = bdf.clone().lazy().filter(col("imputed").not());
+    let timestamp_type = bdf.schema().try_get("timestamp")?.clone();
+
+    let bid_events = bids.clone().select([
+        col("username"),
+        col("timestamp").alias("time"),
+        col("delta"),
+        col("price"),
+        lit(true).alias("bid"),
+        lit(false).alias("win"),
+    ]);
+
+    // A win becomes known when its auction ends
+    let win_events = bids
+        .filter(col("final_bid"))
+        .join(
+            adf.clone()
+                .lazy()
+                .select([col("auction_id"), col("end_time")]),
+            [col("auction_id")],
+            [col("auction_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([
+            col("username"),
+            col("end_time").cast(timestamp_type).alias("time"),
+            lit(NULL).cast(DataType::Int64).alias("delta"),
+            lit(NULL).cast(DataType::UInt64).alias("price"),
+            lit(false).alias("bid"),
+            lit(true).alias("win"),
+        ]);
+
+    let events = concat([bid_events, win_events], Default::default())?
+        .sort_by_exprs([col("username"), col("time")], [false, false], false, false)
+        .collect()?;
+
+    let keys = [col("username"), col("time")];
+    let mut windows = config.windows.iter();
+    let Some(first) = windows.next() else {
+        polars_bail!(ComputeError: "no user stats windows configured");
+    };
+    let mut stats = user_window_stats(&events, first)?;
+    for window in windows {
+        stats = stats.join(
+            user_window_stats(&events, window)?,
+            keys.clone(),
+            keys.clone(),
+            JoinArgs::new(JoinType::Left),
+        );
+    }
+
+    Ok(stats.sort_by_exprs(keys, [false, false], false, false))
+}
+
+/// [`USER_STATS`] over the `window` before each event in the sorted `events`, suffixed with the
+/// window name.
+fn user_window_stats(events: &DataFrame, window: &str) -> PolarsResult<LazyFrame> {
+    let period = if window == LIFETIME {
+        // Long enough to reach back to the first event from the last one
+        let times = events.column("time")?.datetime()?;
+        let span = times.max().unwrap_or(0) - times.min().unwrap_or(0) + 1;
+        let unit = match times.time_unit() {
+            TimeUnit::Nanoseconds => "ns",
+            TimeUnit::Microseconds => "us",
+            TimeUnit::Milliseconds => "ms",
+        };
+        format!("{span}{unit}")
+    } else {
+        window.to_owned()
+    };
+
+    let stat = |name: &str| format!("{name}_{window}");
+    Ok(events
+        .clone()
         .lazy()
-        .groupby_dynamic(
-            col("timestamp"),
+        .groupby_rolling(
+            col("time"),
             [col("username")],
-            DynamicGroupOptions {
-                every: Duration::parse("30m"),
-                period: Duration::parse("4d"),
-                offset: Duration::parse("30m"),
+            RollingGroupOptions {
+                period: Duration::parse(&period),
+                offset: Duration::parse(&format!("-{period}")),
+                // [t - period, t), nothing at the time of the bid itself
+                closed_window: ClosedWindow::Left,
+                check_sorted: false,
                 ..Default::default()
             },
         )
         .agg([
-            min("idx").alias("idx"),
-            avg("delta").alias("avg_delta"),
-            col("delta").std(1).alias("std_delta"),
-            count().alias("total_spend"),
-            avg("price").alias("avg_bid_price"),
-            col("price").std(1).alias("std_bid_price"),
-            col("final_bid")
-                .filter(col("final_bid"))
-                .count()
-                .alias("wins"),
+            col("delta").mean().alias(&stat("avg_delta")),
+            col("delta").std(1).alias(&stat("std_delta")),
+            col("bid").sum().alias(&stat("total_spend")),
+            col("price").mean().alias(&stat("avg_bid_price")),
+            col("price").std(1).alias(&stat("std_bid_price")),
+            col("win").sum().alias(&stat("wins")),
+        ])
+        // Empty windows count nothing
+        .with_columns([
+            col(&stat("total_spend")).fill_null(lit(0 as IdxSize)),
+            col(&stat("wins")).fill_null(lit(0 as IdxSize)),
         ])
-        .drop_columns(["timestamp"])
-        .unique(None, UniqueKeepStrategy::First)
-        .sort("username", Default::default())
+        // Events at the same time share a window, keep one row per bid timestamp
+        .unique(
+            Some(vec!["username".into(), "time".into()]),
+            UniqueKeepStrategy::First,
+        ))
 }
 
 pub fn bdf_user_stats_join(bdf: DataFrame, user_stats: &DataFrame) -> LazyFrame {
     bdf.lazy()
         .join(
             user_stats.clone().lazy(),
-            [col("idx")],
-            [col("idx")],
-            JoinArgs::new(JoinType::AsOf(AsOfOptions {
-                left_by: Some(vec!["username".into()]),
-                right_by: Some(vec!["username".into()]),
-                ..Default::default()
-            })),
+            [col("username"), col("timestamp")],
+            [col("username"), col("time")],
+            JoinArgs::new(JoinType::Left),
         )
         .drop_columns(["idx"])
 }
@@ -188,147 +845,1126 @@ pub fn adf_bdf_make_df(adf: DataFrame, bdf: DataFrame) -> LazyFrame {
         .inner_join(adf.lazy(), "auction_id", "auction_id")
 }
 
-pub fn time_series_data(df: &DataFrame) -> LazyFrame {
+/// Buy-it-now economics of every bid in the joined `df`:
+///
+/// - `price_to_bin`: price as a share of `bin_price`
+/// - `effective_price`: price in pennies once `percent_off` is taken off
+/// - `bids_to_break_even`: further bids, at `bid_cost` each, the bidder can place before winning
+///   at the effective price costs more than buying the item outright
+/// - `exchange_covers_bin`: whether the bids the bidder spent are exchangeable and worth the
+///   whole `bin_price`
+pub fn df_bin_economics(df: LazyFrame, bid_cost: u64) -> LazyFrame {
+    let pennies = |name: &str| col(name).cast(DataType::Float64);
+
+    df.with_column(
+        (pennies("price") * (lit(100.) - pennies("percent_off")) / lit(100.))
+            .alias("effective_price"),
+    )
+    .with_columns([
+        when(col("bin_price").gt(0))
+            .then(pennies("price") / pennies("bin_price"))
+            .otherwise(lit(0.))
+            .alias("price_to_bin"),
+        ((pennies("bin_price") - col("effective_price") - pennies("user_auction_spend"))
+            / lit(bid_cost as f64))
+        .alias("bids_to_break_even"),
+        col("exchangeable")
+            .and(col("user_auction_spend").gt_eq(col("bin_price")))
+            .alias("exchange_covers_bin"),
+    ])
+}
+
+/// Columns identifying a bid, carried by every per bid split file.
+pub const ROW_KEY: [&str; 2] = ["auction_id", "price"];
+
+/// Sort bids by [`ROW_KEY`], the order every per bid split file is written in.
+pub fn sort_by_row_key(df: &DataFrame) -> LazyFrame {
     df.clone()
         .lazy()
-        .select([
-            col("auction_id"),
-            col("prior_bid_dist").alias("prior_bid_dist_0"),
-            col("delta").alias("delta_0"),
-            col("avg_delta").alias("avg_delta_0"),
-            col("std_delta").alias("std_delta_0"),
-            col("total_spend").alias("total_spend_0"),
-            col("avg_bid_price").alias("avg_bid_price_0"),
-            col("std_bid_price").alias("std_bid_price_0"),
-            col("wins").alias("wins_0"),
-        ])
+        .sort_by_exprs(ROW_KEY.map(col), [false, false], false, true)
+}
+
+/// Per bid features fed to the sequence model, followed by the user stats of each window.
+pub const BID_FEATURES: [&str; 7] = [
+    "prior_bid_dist",
+    "delta",
+    "clock_remaining_at_bid",
+    "clock_reset_to",
+    "time_since_start",
+    "imputed",
+    "delta_imputed",
+];
+
+/// How contested the auction is at each bid, see [`bdf_competition_features`].
+pub const COMPETITION_FEATURES: [&str; 5] = [
+    "bidders_last_bids",
+    "bidders_last_time",
+    "bid_share_hhi",
+    "committed_bidders",
+    "is_leader",
+];
+
+/// What the bidder has sunk into the auction, see [`adf_bdf_user_auction_features`].
+pub const USER_AUCTION_FEATURES: [&str; 5] = [
+    "user_auction_bids",
+    "user_auction_spend",
+    "user_first_bid_price",
+    "user_bids_per_minute",
+    "user_entry_vs_no_jumper",
+];
+
+/// Buy-it-now economics of each bid, see [`df_bin_economics`].
+pub const BIN_FEATURES: [&str; 4] = [
+    "price_to_bin",
+    "effective_price",
+    "bids_to_break_even",
+    "exchange_covers_bin",
+];
+
+/// Time series feature columns for the user stats `windows`, in column order.
+pub fn time_series_features(windows: &[String]) -> Vec<String> {
+    BID_FEATURES
+        .iter()
+        .chain(&COMPETITION_FEATURES)
+        .chain(&USER_AUCTION_FEATURES)
+        .chain(&BIN_FEATURES)
+        .map(|&feature| feature.to_owned())
+        .chain(windows.iter().flat_map(|window| {
+            USER_STATS
+                .iter()
+                .map(move |stat| format!("{stat}_{window}"))
+        }))
+        .collect()
+}
+
+/// Each feature of the current bid and the `lookback - 1` bids before it, as `{feature}_{steps back}`.
+pub fn time_series_data(df: &DataFrame, features: &[String], lookback: usize) -> LazyFrame {
+    sort_by_row_key(df)
+        .select(
+            ROW_KEY
+                .map(col)
+                .into_iter()
+                .chain(
+                    features
+                        .iter()
+                        .map(|feature| col(feature).alias(&format!("{feature}_0"))),
+                )
+                .collect::<Vec<Expr>>(),
+        )
         .with_columns(
-            (1..9)
+            (1..lookback as i64)
                 .flat_map(|index| {
-                    [
-                        col("prior_bid_dist_0")
+                    features.iter().map(move |feature| {
+                        col(&format!("{feature}_0"))
                             .shift(index)
                             .over(["auction_id"])
-                            .alias(format!("prior_bid_dist_{index}").as_str()),
-                        col("delta_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("delta_{index}").as_str()),
-                        col("avg_delta_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("avg_delta_{index}").as_str()),
-                        col("std_delta_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("std_delta_{index}").as_str()),
-                        col("total_spend_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("total_spend_{index}").as_str()),
-                        col("avg_bid_price_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("avg_bid_price_{index}").as_str()),
-                        col("std_bid_price_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("std_bid_price_{index}").as_str()),
-                        col("wins_0")
-                            .shift(index)
-                            .over(["auction_id"])
-                            .alias(format!("wins_{index}").as_str()),
-                    ]
+                            .alias(&format!("{feature}_{index}"))
+                    })
                 })
                 .collect::<Vec<Expr>>(),
         )
-        .drop_columns(["auction_id"])
 }
 
-pub fn meta_data(df: &DataFrame) -> LazyFrame {
-    df.clone().lazy().select([
-        col("price"),
-        col("bin_price"),
-        col("no_jumper_limit"),
-        col("exchangeable"),
-        col("one_per_user"),
-        col("no_re_entry"),
-        col("is_bindolence"),
-        col("percent_off"),
-        col("start_hour_sin"),
-        col("start_hour_cos"),
-        col("start_minute_sin"),
-        col("start_minute_cos"),
-    ])
+/// Auction level features repeated on every bid.
+pub fn meta_features(feature_set: FeatureSet) -> Vec<&'static str> {
+    [
+        "price",
+        "bin_price",
+        "no_jumper_limit",
+        "exchangeable",
+        "one_per_user",
+        "no_re_entry",
+        "is_bindolence",
+        "percent_off",
+    ]
+    .into_iter()
+    .chain(start_time_features(feature_set))
+    .collect()
+}
+
+/// The [`meta_features`] of every bid, led by the [`ROW_KEY`].
+pub fn meta_data(df: &DataFrame, feature_set: FeatureSet) -> LazyFrame {
+    sort_by_row_key(df).select(
+        ROW_KEY
+            .into_iter()
+            .chain(
+                meta_features(feature_set)
+                    .into_iter()
+                    .filter(|feature| !ROW_KEY.contains(feature)),
+            )
+            .map(col)
+            .collect::<Vec<Expr>>(),
+    )
+}
+
+/// Each auction's bids as one row of list columns, in price order.
+///
+/// Auctions with more than `max_len` bids are cut into consecutive chunks of at most `max_len`
+/// bids. `seq_len` holds the length of every list in the row, so padding and masks can be built
+/// at training time.
+pub fn sequence_data(
+    df: &DataFrame,
+    features: &[String],
+    feature_set: FeatureSet,
+    max_len: usize,
+) -> LazyFrame {
+    let columns = features
+        .iter()
+        .map(String::as_str)
+        .chain(meta_features(feature_set))
+        .chain(["final_bid"])
+        .map(col);
+
+    df.clone()
+        .lazy()
+        .sort_by_exprs(
+            [col("auction_id"), col("price")],
+            [false, false],
+            false,
+            false,
+        )
+        .with_column(
+            (col("price")
+                .cumcount(false)
+                .over(["auction_id"])
+                .cast(DataType::Float64)
+                / lit(max_len as f64))
+            .cast(DataType::UInt32)
+            .alias("chunk"),
+        )
+        .groupby_stable([col("auction_id"), col("chunk")])
+        .agg(
+            std::iter::once(count().alias("seq_len"))
+                .chain(columns)
+                .collect::<Vec<Expr>>(),
+        )
 }
 
 pub fn y_data(df: &DataFrame) -> LazyFrame {
-    df.clone().lazy().select([col("final_bid")])
+    sort_by_row_key(df).select([col("auction_id"), col("price"), col("final_bid")])
 }
 
-pub fn train_test_split(df: &DataFrame) -> (LazyFrame, LazyFrame, LazyFrame) {
-    let valid_aucs = df
-        .clone()
+/// `fraction` of `auctions`, rounded to the nearest auction so float error in shares like `0.7`
+/// does not drop one.
+fn share(auctions: usize, fraction: f64) -> usize {
+    (auctions as f64 * fraction).round() as usize
+}
+
+/// Index of the first val and the first test auction when `auctions` chronologically ordered
+/// auctions are split by `config`. Train gets `[0, val_start)`, val `[val_start, test_start)` and
+/// test `[test_start, auctions)`.
+pub fn split_bounds(auctions: usize, config: &SplitConfig) -> (usize, usize) {
+    let val_start = share(auctions, config.train).min(auctions);
+    let test_start = share(auctions, config.train + config.val).clamp(val_start, auctions);
+    (val_start, test_start)
+}
+
+/// Auctions of `df` that start after the warm-up, in start order and numbered by `idx`.
+///
+/// The warm-up is measured from the first bid, where the user stats windows begin.
+fn ordered_auctions(df: &DataFrame, config: &SplitConfig) -> PolarsResult<DataFrame> {
+    df.clone()
         .lazy()
-        .select([col("auction_id"), col("start_time")])
-        // Remove historical starting buffer (first 4 days 1 hour)
-        .filter(
-            (col("start_time") - col("start_time").min()).gt(lit(chrono::Duration::days(4)
-                .checked_add(&chrono::Duration::hours(1))
-                .unwrap())),
+        .filter((col("start_time") - col("timestamp").min()).gt_eq(lit(config.warmup())))
+        .groupby([col("auction_id")])
+        .agg([col("start_time").first(), col("end_time").first()])
+        .sort_by_exprs(
+            [col("start_time"), col("auction_id")],
+            [false, false],
+            false,
+            false,
         )
-        .unique(None, UniqueKeepStrategy::First)
-        .sort("start_time", Default::default())
         .with_row_count("idx", None)
         .collect()
-        .unwrap();
+}
+
+/// Selects the `[start, end)` range of auctions numbered by [`ordered_auctions`].
+fn idx_range(start: usize, end: usize) -> Expr {
+    col("idx")
+        .gt_eq(lit(start as IdxSize))
+        .and(col("idx").lt(lit(end as IdxSize)))
+}
+
+/// Bids of the auctions in `aucs`.
+fn auction_bids(df: &DataFrame, aucs: LazyFrame) -> LazyFrame {
+    df.clone().lazy().inner_join(
+        aucs.select([col("auction_id")]),
+        col("auction_id"),
+        col("auction_id"),
+    )
+}
 
-    let (row_count, _) = valid_aucs.shape();
-    let row_count = row_count as u64;
+/// Ids of the auctions [`train_test_split`] puts in train.
+fn train_auctions(df: &DataFrame, config: &SplitConfig) -> PolarsResult<LazyFrame> {
+    let valid_aucs = ordered_auctions(df, config)?;
+    let (val_start, _) = split_bounds(valid_aucs.height(), config);
+    Ok(valid_aucs
+        .lazy()
+        .filter(idx_range(0, val_start))
+        .select([col("auction_id")]))
+}
 
-    let train_val_split_idx = row_count * 6 / 10;
-    let val_test_split_idx = row_count * 8 / 10;
+/// Split `df` chronologically by auction start into train, val and test.
+///
+/// Auctions starting within the warm-up are left out of every split.
+pub fn train_test_split(
+    df: &DataFrame,
+    config: &SplitConfig,
+) -> PolarsResult<(LazyFrame, LazyFrame, LazyFrame)> {
+    let valid_aucs = ordered_auctions(df, config)?;
+    let auctions = valid_aucs.height();
+    let (val_start, test_start) = split_bounds(auctions, config);
 
     debug!(
-        "Split sizes: (train {}, val {}, test {})",
-        train_val_split_idx,
-        val_test_split_idx - train_val_split_idx,
-        row_count - val_test_split_idx
+        "Split sizes: (train {}, val {}, test {}), {} auctions skipped for the warm-up",
+        val_start,
+        test_start - val_start,
+        auctions - test_start,
+        df.column("auction_id")?.n_unique()? - auctions,
     );
 
-    let train_aucs = valid_aucs
-        .clone()
-        .lazy()
-        .filter(col("idx").lt_eq(lit(train_val_split_idx)))
-        .select([col("auction_id")]);
+    let split =
+        |start, end| auction_bids(df, valid_aucs.clone().lazy().filter(idx_range(start, end)));
+    Ok((
+        split(0, val_start),
+        split(val_start, test_start),
+        split(test_start, auctions),
+    ))
+}
 
-    let val_aucs = valid_aucs
-        .clone()
-        .lazy()
-        .filter(
-            col("idx")
-                .gt(lit(train_val_split_idx))
-                .and(col("idx").lt_eq(lit(val_test_split_idx))),
-        )
-        .select([col("auction_id")]);
+/// Train and val frames of each walk-forward fold in `config.folds`, oldest fold first.
+///
+/// Folds only cover the train and val auctions of [`train_test_split`], the last validation window
+/// ending right before the test split, so the test auctions stay unseen by every fold.
+///
+/// Training auctions that end within the purge gap before their fold's first validation auction
+/// starts are dropped. Folds too small to hold a validation auction purge nothing.
+pub fn walk_forward_folds(
+    df: &DataFrame,
+    config: &SplitConfig,
+) -> PolarsResult<Vec<(LazyFrame, LazyFrame)>> {
+    let folds = &config.folds;
+    let valid_aucs = ordered_auctions(df, config)?;
+    let auctions = valid_aucs.height();
+    let (_, test_start) = split_bounds(auctions, config);
+    let val_len = share(auctions, folds.val);
+    let train_len = share(auctions, folds.train);
 
-    let test_aucs = valid_aucs
-        .lazy()
-        .filter(col("idx").gt(lit(val_test_split_idx)))
-        .select([col("auction_id")]);
+    (0..folds.k)
+        .map(|k| {
+            let val_start = test_start.saturating_sub((folds.k - k) * val_len);
+            let val_end = (val_start + val_len).min(test_start);
+            let train_start = match folds.window {
+                FoldWindow::Expanding => 0,
+                FoldWindow::Sliding => val_start.saturating_sub(train_len),
+            };
 
-    let train = df
-        .clone()
+            let mut train_filter = idx_range(train_start, val_start);
+            if folds.purge() > chrono::Duration::zero() && val_len > 0 {
+                let val_start_time = valid_aucs.column("start_time")?.slice(val_start as i64, 1);
+                train_filter = train_filter
+                    .and((lit(val_start_time) - col("end_time")).gt(lit(folds.purge())));
+            }
+            let train_aucs = valid_aucs.clone().lazy().filter(train_filter);
+            let val_aucs = valid_aucs
+                .clone()
+                .lazy()
+                .filter(idx_range(val_start, val_end));
+
+            debug!(
+                "Fold {}: train auctions [{}, {}), val auctions [{}, {})",
+                k, train_start, val_start, val_start, val_end
+            );
+            Ok((auction_bids(df, train_aucs), auction_bids(df, val_aucs)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime};
+
+    use super::*;
+    use crate::config::FoldConfig;
+
+    fn day(n: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 1, 1)
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .unwrap()
+            + ChronoDuration::days(n)
+    }
+
+    fn minute(n: i64) -> NaiveDateTime {
+        day(0) + ChronoDuration::minutes(n)
+    }
+
+    fn split_config(train: f64, val: f64) -> SplitConfig {
+        SplitConfig {
+            warmup: "1d".into(),
+            train,
+            val,
+            ..Default::default()
+        }
+    }
+
+    /// Two bids per auction, auction `n` starts on day `n` and ends 12 hours later. The first bid
+    /// is placed on day 0.
+    fn auctions(count: i32) -> DataFrame {
+        let ids = (0..count).flat_map(|id| [id, id]).collect::<Vec<i32>>();
+        let starts = ids.iter().map(|&id| day(id as i64)).collect::<Vec<_>>();
+        let ends = starts
+            .iter()
+            .map(|&start| start + ChronoDuration::hours(12))
+            .collect::<Vec<_>>();
+        let timestamps = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| start + ChronoDuration::minutes(i as i64 % 2))
+            .collect::<Vec<_>>();
+
+        df! [
+            "auction_id" => ids,
+            "start_time" => starts,
+            "end_time"   => ends,
+            "timestamp"  => timestamps,
+        ]
+        .unwrap()
+    }
+
+    fn ids(lf: LazyFrame) -> Vec<i32> {
+        let df = lf.collect().unwrap();
+        let mut ids = df
+            .column("auction_id")
+            .unwrap()
+            .i32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// The start time encoding of auctions starting at `starts`, in UTC on the site clock.
+    fn start_time_encoding(starts: &[NaiveDateTime], feature_set: FeatureSet) -> DataFrame {
+        let adf = df! [ "start_time" => starts ].unwrap();
+        let adf = tz::frame_to_utc(adf, &["start_time"], tz::UTC).unwrap();
+        adf_sin_cos_start_time(adf.lazy(), feature_set, tz::UTC)
+            .collect()
+            .unwrap()
+    }
+
+    fn points(df: &DataFrame, name: &str) -> Vec<(f64, f64)> {
+        let values = |suffix: &str| {
+            df.column(&format!("{name}_{suffix}"))
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>()
+        };
+        values("sin").into_iter().zip(values("cos")).collect()
+    }
+
+    fn distance((sin_a, cos_a): (f64, f64), (sin_b, cos_b): (f64, f64)) -> f64 {
+        (sin_a - sin_b).hypot(cos_a - cos_b)
+    }
+
+    #[test]
+    fn v2_start_time_wraps_around() {
+        // 23:59 and the following 00:00, then 00:01 as the neighbour on the other side
+        let starts = [minute(-1), minute(0), minute(1), minute(60)];
+        let df = start_time_encoding(&starts, FeatureSet::V2);
+
+        let hours = points(&df, "start_hour");
+        let hour_step = 2. * (PI / 24.).sin();
+        assert!((distance(hours[0], hours[1]) - hour_step).abs() < 1e-9);
+        assert!((distance(hours[1], hours[3]) - hour_step).abs() < 1e-9);
+
+        let minutes = points(&df, "start_minute");
+        let minute_step = 2. * (PI / 60.).sin();
+        assert!((distance(minutes[0], minutes[1]) - minute_step).abs() < 1e-9);
+        assert!((distance(minutes[1], minutes[2]) - minute_step).abs() < 1e-9);
+    }
+
+    #[test]
+    fn v1_start_time_keeps_the_original_encoding() {
+        let starts = [minute(-1), minute(0), minute(5 * 60 + 30)];
+        let df = start_time_encoding(&starts, FeatureSet::V1);
+
+        // The values the original code computed as `x / 23. * PI * 2.`
+        let original = |x: f64| ((x / 23. * PI * 2.).sin(), (x / 23. * PI * 2.).cos());
+        for (name, values) in [
+            ("start_hour", [23., 0., 5.]),
+            ("start_minute", [59., 0., 30.]),
+        ] {
+            for (point, x) in points(&df, name).into_iter().zip(values) {
+                assert!(distance(point, original(x)) < 1e-9, "{name} {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn quality_flags_name_every_failed_check() {
+        // Auction 1 is clean, auctions 2 to 7 each fail the check at the same position in
+        // QUALITY_CHECKS. All run from minute 0 to minute 10, except auction 5 which never ended.
+        let adf = df! [
+            "auction_id" => [1, 2, 3, 4, 5, 6, 7],
+            "start_time" => [minute(0); 7],
+            "end_time"   => [1, 2, 3, 4, 5, 6, 7].map(|id| (id != 5).then(|| minute(10))),
+        ]
+        .unwrap();
+        let bdf = df! [
+            "auction_id" => [1, 1, 3, 3, 4, 4, 4, 5, 6, 7],
+            "price"      => [1u64, 2, 1, 3, 1, 2, 2, 1, 1, 1],
+            "timestamp"  => [1, 2, 1, 2, 1, 2, 3, 1, 11, -1].map(minute),
+        ]
+        .unwrap();
+
+        let flags = adf_bdf_quality_flags(&adf, &bdf).unwrap();
+        assert_eq!(ids(flags.clone().lazy()), [2, 3, 4, 5, 6, 7]);
+        for (check, id) in QUALITY_CHECKS.iter().zip(2..) {
+            assert_eq!(
+                ids(flags.clone().lazy().filter(col(check))),
+                [id],
+                "{check}"
+            );
+        }
+
+        let summary = quality_summary(&flags, adf.height()).unwrap();
+        let reasons = summary.column("reason").unwrap().utf8().unwrap();
+        assert_eq!(
+            reasons.into_no_null_iter().collect::<Vec<_>>(),
+            QUALITY_CHECKS
+                .iter()
+                .copied()
+                .chain(["dropped"])
+                .collect::<Vec<_>>()
+        );
+        let counts = summary.column("auctions").unwrap().u64().unwrap();
+        assert_eq!(
+            counts.into_no_null_iter().collect::<Vec<_>>(),
+            [1, 1, 1, 1, 1, 1, 6]
+        );
+        let shares = summary.column("share").unwrap().f64().unwrap();
+        assert_eq!(shares.get(6), Some(6. / 7.));
+    }
+
+    #[test]
+    fn repair_fills_gaps_of_auctions_missing_few_bids() {
+        // Auction 1 misses 2 of its 6 bids, the first one right after the start. Auction 2 misses
+        // 3 of its 5 bids, more than the 40% that get repaired.
+        let adf = df! [
+            "auction_id" => [1, 2],
+            "start_time" => [minute(0); 2],
+            "end_time"   => [minute(10); 2],
+        ]
+        .unwrap();
+        let bdf = df! [
+            "auction_id" => [1, 1, 1, 1, 2, 2],
+            "price"      => [2u64, 3, 5, 6, 1, 5],
+            "timestamp"  => [2, 3, 7, 8, 1, 5].map(minute),
+            "username"   => ["a", "b", "a", "b", "a", "b"],
+        ]
+        .unwrap();
+        let adf = tz::frame_to_utc(adf, &["start_time", "end_time"], tz::UTC).unwrap();
+        let bdf = tz::frame_to_utc(bdf, &["timestamp"], tz::UTC).unwrap();
+        let flags = adf_bdf_quality_flags(&adf, &bdf).unwrap();
+
+        let (flags, bdf) = adf_bdf_repair_gaps(&adf, bdf, flags, 0.4).unwrap();
+        assert_eq!(ids(flags.lazy()), [2]);
+
+        let bids = bdf
+            .lazy()
+            .sort_by_exprs(
+                [col("auction_id"), col("price")],
+                [false, false],
+                false,
+                false,
+            )
+            .select([
+                col("price").cast(DataType::Int64),
+                col("timestamp").dt().timestamp(TimeUnit::Milliseconds),
+                col("username").is_null().alias("anonymous"),
+                col("imputed"),
+            ])
+            .collect()
+            .unwrap();
+        let ints = |name: &str| bids.column(name).unwrap().i64().unwrap().to_vec();
+        let bools = |name: &str| {
+            bids.column(name)
+                .unwrap()
+                .bool()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        let imputed = [true, false, false, true, false, false, false, false].map(Some);
+
+        assert_eq!(ints("price"), [1, 2, 3, 4, 5, 6, 1, 5].map(Some));
+        // Holes are interpolated between their neighbours, the start standing in for price 0
+        assert_eq!(
+            ints("timestamp"),
+            [1, 2, 3, 5, 7, 8, 1, 5].map(|n| Some(minute(n).timestamp_millis()))
+        );
+        assert_eq!(bools("anonymous"), imputed);
+        assert_eq!(bools("imputed"), imputed);
+    }
+
+    #[test]
+    fn sequences_are_chunked_in_price_order() {
+        let meta = meta_features(FeatureSet::V2)
+            .into_iter()
+            .filter(|&c| c != "price")
+            .map(|c| lit(0).alias(c))
+            .collect::<Vec<_>>();
+        let df = df! [
+            "auction_id" => [1, 2, 1, 1, 2, 1, 1],
+            "price"      => [3u64, 2, 1, 5, 1, 2, 4],
+            "bid_gap"    => [30., 20., 10., 50., 10., 20., 40.],
+            "final_bid"  => [false, true, false, true, false, false, false],
+        ]
+        .unwrap()
         .lazy()
-        .inner_join(train_aucs, col("auction_id"), col("auction_id"));
-    let val = df
-        .clone()
+        .with_columns(meta)
+        .collect()
+        .unwrap();
+
+        let seqs = sequence_data(&df, &["bid_gap".to_owned()], FeatureSet::V2, 2)
+            .collect()
+            .unwrap();
+        let column = |name: &str| seqs.column(name).unwrap().clone();
+        let lists = |name: &str| {
+            column(name)
+                .list()
+                .unwrap()
+                .into_iter()
+                .map(|s| s.unwrap().cast(&DataType::Float64).unwrap())
+                .map(|s| s.f64().unwrap().into_no_null_iter().collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            column("auction_id").i32().unwrap().to_vec(),
+            [1, 1, 1, 2].map(Some)
+        );
+        assert_eq!(
+            column("chunk").u32().unwrap().to_vec(),
+            [0, 1, 2, 0].map(Some)
+        );
+        assert_eq!(
+            column("seq_len").u32().unwrap().to_vec(),
+            [2, 2, 1, 2].map(Some)
+        );
+        assert_eq!(
+            lists("price"),
+            [vec![1., 2.], vec![3., 4.], vec![5.], vec![1., 2.]]
+        );
+        // Features stay aligned with the prices they were computed for
+        assert_eq!(
+            lists("bid_gap"),
+            [vec![10., 20.], vec![30., 40.], vec![50.], vec![10., 20.]]
+        );
+    }
+
+    #[test]
+    fn bounds_are_half_open() {
+        assert_eq!(split_bounds(10, &split_config(0.6, 0.2)), (6, 8));
+        assert_eq!(split_bounds(9, &split_config(0.5, 0.3)), (5, 7));
+        assert_eq!(split_bounds(10, &split_config(0.7, 0.1)), (7, 8));
+        assert_eq!(split_bounds(5, &split_config(1., 0.)), (5, 5));
+        assert_eq!(split_bounds(5, &split_config(0., 0.)), (0, 0));
+        assert_eq!(split_bounds(0, &split_config(0.6, 0.2)), (0, 0));
+    }
+
+    #[test]
+    fn splits_are_disjoint_and_complete() {
+        let df = auctions(11);
+        let (train, val, test) = train_test_split(&df, &split_config(0.6, 0.2)).unwrap();
+        let (train, val, test) = (ids(train), ids(val), ids(test));
+
+        // Auction 0 starts within the warm-up, the other 10 are split 6/2/2 in start order
+        assert_eq!(train, (1..7).collect::<Vec<_>>());
+        assert_eq!(val, [7, 8]);
+        assert_eq!(test, [9, 10]);
+
+        let all = train
+            .iter()
+            .chain(&val)
+            .chain(&test)
+            .collect::<HashSet<_>>();
+        assert_eq!(all.len(), train.len() + val.len() + test.len());
+        assert_eq!(all, (1..11).collect::<Vec<_>>().iter().collect());
+    }
+
+    #[test]
+    fn warmup_is_measured_from_the_first_bid() {
+        // Started long before bids were recorded, so its start is not where the stats begin
+        let early = df! [
+            "auction_id" => [100],
+            "start_time" => [day(-3)],
+            "end_time"   => [day(0) + ChronoDuration::hours(1)],
+            "timestamp"  => [day(0) + ChronoDuration::minutes(5)],
+        ]
+        .unwrap();
+        let df = early.vstack(&auctions(3)).unwrap();
+
+        let (train, val, test) = train_test_split(&df, &split_config(1., 0.)).unwrap();
+        assert_eq!(ids(train), [1, 2]);
+        assert!(ids(val).is_empty());
+        assert!(ids(test).is_empty());
+    }
+
+    fn fold_config(k: usize, window: FoldWindow, val: f64, purge: &str) -> SplitConfig {
+        SplitConfig {
+            folds: FoldConfig {
+                k,
+                window,
+                train: 0.3,
+                val,
+                purge: purge.into(),
+            },
+            ..split_config(0.6, 0.2)
+        }
+    }
+
+    fn fold_ids(df: &DataFrame, config: &SplitConfig) -> Vec<(Vec<i32>, Vec<i32>)> {
+        walk_forward_folds(df, config)
+            .unwrap()
+            .into_iter()
+            .map(|(train, val)| (ids(train), ids(val)))
+            .collect()
+    }
+
+    #[test]
+    fn folds_walk_forward_up_to_the_test_split() {
+        // Auction 0 starts within the warm-up, auctions 9 and 10 are the test split and the other
+        // 8 are left for the folds
+        let df = auctions(11);
+
+        let expanding = fold_ids(&df, &fold_config(2, FoldWindow::Expanding, 0.2, "0s"));
+        assert_eq!(
+            expanding,
+            [
+                ((1..5).collect::<Vec<_>>(), vec![5, 6]),
+                ((1..7).collect::<Vec<_>>(), vec![7, 8]),
+            ]
+        );
+
+        let sliding = fold_ids(&df, &fold_config(2, FoldWindow::Sliding, 0.2, "0s"));
+        assert_eq!(
+            sliding,
+            [(vec![2, 3, 4], vec![5, 6]), (vec![4, 5, 6], vec![7, 8])]
+        );
+
+        for (train, val) in expanding.iter().chain(&sliding) {
+            assert!(train.iter().all(|id| !val.contains(id)));
+            assert!(train.iter().chain(val).all(|&id| id < 9));
+        }
+    }
+
+    #[test]
+    fn purge_drops_training_auctions_ending_near_each_fold() {
+        // Auction n ends at noon on day n, so with a 1 day purge the auction starting the day
+        // before the first validation auction is dropped from each fold
+        let df = auctions(11);
+
+        let folds = fold_ids(&df, &fold_config(2, FoldWindow::Expanding, 0.2, "1d"));
+        assert_eq!(
+            folds,
+            [
+                ((1..4).collect::<Vec<_>>(), vec![5, 6]),
+                ((1..6).collect::<Vec<_>>(), vec![7, 8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn purge_keeps_training_auctions_without_validation_auctions() {
+        // 10 auctions are too few for a validation window of 4%
+        let df = auctions(11);
+
+        let folds = fold_ids(&df, &fold_config(1, FoldWindow::Expanding, 0.04, "1d"));
+        assert_eq!(folds, [((1..9).collect::<Vec<_>>(), Vec::new())]);
+    }
+
+    fn clock_bands(bands: &[(u64, Option<&str>)]) -> Vec<ClockBand> {
+        bands
+            .iter()
+            .map(|&(from_price, reset_to)| ClockBand {
+                from_price,
+                reset_to: reset_to.map(String::from),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clock_is_calibrated_from_train_auctions_only() {
+        // One bid per auction at its start, auction n starts on day n. Auction 0 falls in the
+        // warm-up, 1 and 2 in train, 3 in val and 4 in test.
+        let second = ChronoDuration::seconds;
+        let bdf = df! [
+            "auction_id" => [0, 1, 2, 3, 4],
+            "price"      => [2u64, 2, 5, 2, 5],
+            "timestamp"  => [day(0), day(1), day(2), day(3), day(4)],
+            "final_bid"  => [true; 5],
+        ]
+        .unwrap();
+        let adf = df! [
+            "auction_id" => [0, 1, 2, 3, 4],
+            "start_time" => [day(0), day(1), day(2), day(3), day(4)],
+            "end_time"   => [
+                day(0) + second(100),
+                day(1) + second(8),
+                day(2) + second(4),
+                day(3) + second(100),
+                day(4) + second(100),
+            ],
+        ]
+        .unwrap();
+        let bands = clock_bands(&[(0, None), (3, None), (10, Some("30s"))]);
+
+        let resets = calibrate_clock(&adf, &bdf, &bands, &split_config(0.5, 0.25)).unwrap();
+        assert_eq!(resets, [8_000, 4_000, 30_000]);
+    }
+
+    #[test]
+    fn clock_resets_to_the_band_of_each_bid() {
+        let bdf = df! [
+            "auction_id" => [1, 1, 1, 1, 2],
+            "price"      => [1u64, 2, 3, 4, 1],
+            "delta"      => [5_000i64, 3_000, 1_000, 2_000, 7_000],
+        ]
+        .unwrap();
+        let bands = clock_bands(&[(0, Some("20s")), (3, Some("10s"))]);
+
+        let df = bdf_clock_features(bdf, &bands, &[20_000, 10_000])
+            .sort_by_exprs(
+                [col("auction_id"), col("price")],
+                [false, false],
+                false,
+                false,
+            )
+            .collect()
+            .unwrap();
+        let values = |name: &str| {
+            df.column(name)
+                .unwrap()
+                .i64()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            values("clock_reset_to"),
+            [
+                Some(20_000),
+                Some(20_000),
+                Some(10_000),
+                Some(10_000),
+                Some(20_000)
+            ]
+        );
+        // Price 3 lands on the timer set by price 2, price 4 on the one set by price 3
+        assert_eq!(
+            values("clock_remaining_at_bid"),
+            [None, Some(17_000), Some(19_000), Some(8_000), None]
+        );
+    }
+
+    #[test]
+    fn user_auction_features_count_each_bidders_bids() {
+        let second = |n| minute(0) + ChronoDuration::seconds(n);
+        let adf = df! [
+            "auction_id"      => [1, 2],
+            "no_jumper_limit" => [0u64, 100],
+        ]
+        .unwrap()
+        .lazy();
+        let bdf = df! [
+            "auction_id" => [1, 1, 1, 1, 2, 2],
+            "price"      => [1u64, 2, 3, 4, 10, 11],
+            "timestamp"  => [0, 30, 60, 90, 0, 120].map(second),
+            "username"   => ["a", "b", "a", "a", "c", "c"],
+        ]
+        .unwrap()
+        .lazy();
+
+        let df = adf_bdf_user_auction_features(&adf, bdf, 15)
+            .sort_by_exprs(
+                [col("auction_id"), col("price")],
+                [false, false],
+                false,
+                false,
+            )
+            .collect()
+            .unwrap();
+        let values = |name: &str| {
+            df.column(name)
+                .unwrap()
+                .cast(&DataType::Float64)
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(values("user_auction_bids"), [1., 1., 2., 3., 1., 2.]);
+        assert_eq!(values("user_auction_spend"), [15., 15., 30., 45., 15., 30.]);
+        assert_eq!(values("user_first_bid_price"), [1., 2., 1., 1., 10., 10.]);
+        // The first bid has no rate yet, later ones count the bids after it
+        assert_eq!(
+            values("user_bids_per_minute"),
+            [0., 0., 1., 2. / 1.5, 0., 0.5]
+        );
+        // Auction 1 has no no-jumper limit
+        assert_eq!(
+            values("user_entry_vs_no_jumper"),
+            [0., 0., 0., 0., 0.1, 0.1]
+        );
+    }
+
+    #[test]
+    fn bin_economics_account_for_discount_and_spend() {
+        let df = df! [
+            "price"              => [200u64, 500, 100],
+            "bin_price"          => [1000u64, 1000, 0],
+            "percent_off"        => [50, 0, 0],
+            "user_auction_spend" => [600u64, 1200, 1200],
+            "exchangeable"       => [true, true, false],
+        ]
+        .unwrap();
+
+        let df = df_bin_economics(df.lazy(), 20).collect().unwrap();
+        let values = |name: &str| {
+            df.column(name)
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(values("price_to_bin"), [0.2, 0.5, 0.]);
+        assert_eq!(values("effective_price"), [100., 500., 100.]);
+        // Half off leaves 1000 - 100 - 600 pennies, 15 more bids at 20 pennies
+        assert_eq!(values("bids_to_break_even"), [15., -35., -65.]);
+        assert_eq!(
+            df.column("exchange_covers_bin")
+                .unwrap()
+                .bool()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+    }
+
+    /// Stats of `user`'s bids, in time order.
+    fn user_stats_at(
+        bdf: &DataFrame,
+        adf: &DataFrame,
+        user: &str,
+    ) -> (Vec<Option<u32>>, Vec<Option<u32>>) {
+        let config = UserStatsConfig {
+            windows: vec!["1d".into()],
+        };
+        let stats = bdf_user_historical_stats(bdf, adf, &config)
+            .unwrap()
+            .collect()
+            .unwrap();
+        let joined = bdf_user_stats_join(bdf.clone(), &stats)
+            .filter(col("username").eq(lit(user)))
+            .sort("timestamp", Default::default())
+            .collect()
+            .unwrap();
+
+        let column = |name: &str| -> Vec<Option<u32>> {
+            joined
+                .column(name)
+                .unwrap()
+                .cast(&DataType::UInt32)
+                .unwrap()
+                .u32()
+                .unwrap()
+                .into_iter()
+                .collect()
+        };
+        (column("wins_1d"), column("total_spend_1d"))
+    }
+
+    #[test]
+    fn user_stats_only_count_wins_of_ended_auctions() {
+        // Auction 1 is won by `a` at minute 2 and ends at minute 10, auction 2 ends at minute 30.
+        // `a` bids in auction 2 while auction 1 is still running, right when it ends, and after.
+        let bdf = df! [
+            "auction_id" => [1, 1, 2, 2, 2, 2],
+            "price"      => [1u64, 2, 1, 2, 3, 4],
+            "timestamp"  => [minute(1), minute(2), minute(5), minute(10), minute(12), minute(20)],
+            "username"   => ["b", "a", "a", "a", "b", "a"],
+            "delta"      => [1i64, 1, 1, 1, 1, 1],
+            "final_bid"  => [false, true, false, false, false, true],
+            "imputed"    => [false; 6],
+            "idx"        => [0u32, 1, 2, 3, 4, 5],
+        ]
+        .unwrap();
+        let adf = df! [
+            "auction_id" => [1, 2],
+            "end_time"   => [minute(10), minute(30)],
+        ]
+        .unwrap();
+
+        let (wins, total_spend) = user_stats_at(&bdf, &adf, "a");
+
+        // The win only counts once auction 1 has ended, and the win of auction 2 never counts
+        assert_eq!(wins, [Some(0), Some(0), Some(0), Some(1)]);
+        // Earlier bids count, the bid being scored does not
+        assert_eq!(total_spend, [Some(0), Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn user_stats_skip_placeholder_bids() {
+        let bdf = df! [
+            "auction_id" => [1, 1, 1],
+            "price"      => [1u64, 2, 3],
+            "timestamp"  => [minute(1), minute(2), minute(3)],
+            "username"   => [Some("a"), None, Some("a")],
+            "delta"      => [1i64, 1, 1],
+            "final_bid"  => [false, false, true],
+            "imputed"    => [false, true, false],
+            "idx"        => [0u32, 1, 2],
+        ]
+        .unwrap();
+        let adf = df! [
+            "auction_id" => [1],
+            "end_time"   => [minute(4)],
+        ]
+        .unwrap();
+
+        let (wins, total_spend) = user_stats_at(&bdf, &adf, "a");
+        assert_eq!(wins, [Some(0), Some(0)]);
+        assert_eq!(total_spend, [Some(0), Some(1)]);
+    }
+
+    /// Auction 1 starts at minute 0 and gets bids 1.5s, 4s and 64s after it starts, with both
+    /// columns stored in the given units.
+    fn delta_fixture(start_unit: TimeUnit, bid_unit: TimeUnit) -> (LazyFrame, LazyFrame) {
+        let millis = |n| minute(0) + ChronoDuration::milliseconds(n);
+        let adf = df! [
+            "auction_id" => [1],
+            "start_time" => [minute(0)],
+        ]
+        .unwrap()
         .lazy()
-        .inner_join(val_aucs, col("auction_id"), col("auction_id"));
-    let test = df
-        .clone()
+        .with_column(col("start_time").cast(DataType::Datetime(start_unit, None)));
+        let bdf = df! [
+            "auction_id" => [1, 1, 1],
+            "price"      => [3u64, 1, 2],
+            "timestamp"  => [millis(64_000), millis(1_500), millis(4_000)],
+            "imputed"    => [false; 3],
+        ]
+        .unwrap()
         .lazy()
-        .inner_join(test_aucs, col("auction_id"), col("auction_id"));
+        .with_column(col("timestamp").cast(DataType::Datetime(bid_unit, None)));
+        (adf, bdf)
+    }
+
+    #[test]
+    fn deltas_are_positive_milliseconds_in_any_unit() {
+        let units = [
+            TimeUnit::Nanoseconds,
+            TimeUnit::Microseconds,
+            TimeUnit::Milliseconds,
+        ];
+        for start_unit in units {
+            for bid_unit in units {
+                let (adf, bdf) = delta_fixture(start_unit, bid_unit);
+                let df = adf_bdf_calculate_bid_deltas(&adf, bdf)
+                    .sort("price", Default::default())
+                    .collect()
+                    .unwrap();
+                let values = |name: &str| {
+                    df.column(name)
+                        .unwrap()
+                        .i64()
+                        .unwrap()
+                        .into_iter()
+                        .collect::<Vec<_>>()
+                };
+
+                assert_eq!(
+                    values("delta"),
+                    [Some(1_500), Some(2_500), Some(60_000)],
+                    "start in {start_unit:?}, bids in {bid_unit:?}"
+                );
+                assert_eq!(
+                    values("time_since_start"),
+                    [Some(1_500), Some(4_000), Some(64_000)],
+                    "start in {start_unit:?}, bids in {bid_unit:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn competition_features_follow_each_auction() {
+        let second = |n| minute(0) + ChronoDuration::seconds(n);
+        let bdf = df! [
+            "auction_id" => [2, 1, 1, 1, 1, 1, 1],
+            "price"      => [1u64, 1, 2, 3, 4, 5, 6],
+            "timestamp"  => [0, 0, 10, 20, 25, 50, 55].map(second),
+            "username"   => [Some("d"), Some("a"), Some("b"), Some("a"), None, Some("a"), Some("c")],
+        ]
+        .unwrap();
+        let config = CompetitionConfig {
+            last_bids: 3,
+            last: "30s".into(),
+            min_bids: 2,
+        };
+
+        let df = bdf_competition_features(bdf, &config).unwrap();
+        let counts = |name: &str| {
+            df.column(name)
+                .unwrap()
+                .u32()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>()
+        };
 
-    (train, val, test)
+        assert_eq!(counts("bidders_last_bids"), [1, 2, 2, 2, 1, 2, 1]);
+        // The bids at 20s and 25s drop out of the window exactly 30s later
+        assert_eq!(counts("bidders_last_time"), [1, 2, 2, 2, 1, 2, 1]);
+        assert_eq!(counts("committed_bidders"), [0, 0, 1, 1, 1, 1, 0]);
+        assert_eq!(
+            df.column("bid_share_hhi")
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            [1., 0.5, 5. / 9., 5. / 9., 0.625, 0.44, 1.]
+        );
+        assert_eq!(
+            df.column("is_leader")
+                .unwrap()
+                .bool()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [
+                Some(true),
+                Some(true),
+                Some(true),
+                None,
+                Some(true),
+                Some(false),
+                Some(true)
+            ]
+        );
+    }
 }
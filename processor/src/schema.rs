@@ -0,0 +1,191 @@
+use polars::prelude::*;
+use tracing::warn;
+
+use crate::error::ProcessorError;
+
+/// Columns of the raw auction frame the processor relies on.
+///
+/// Datetime columns are accepted in any time unit.
+pub fn auction_schema() -> Schema {
+    Schema::from_iter([
+        Field::new("auction_id", DataType::Int32),
+        Field::new(
+            "start_time",
+            DataType::Datetime(TimeUnit::Milliseconds, None),
+        ),
+        Field::new("bin_price", DataType::UInt64),
+        Field::new("no_jumper_limit", DataType::UInt64),
+        Field::new("exchangeable", DataType::Boolean),
+        Field::new("one_per_user", DataType::Boolean),
+        Field::new("no_re_entry", DataType::Boolean),
+        Field::new("is_bindolence", DataType::Boolean),
+        Field::new("percent_off", DataType::Int32),
+        Field::new("end_time", DataType::Datetime(TimeUnit::Milliseconds, None)),
+    ])
+}
+
+/// Extra auction columns written by `export --all-columns`.
+pub fn auction_detail_schema() -> Schema {
+    Schema::from_iter([
+        Field::new("category_id", DataType::Int32),
+        Field::new("category_name", DataType::Utf8),
+        Field::new("name", DataType::Utf8),
+        Field::new("bin_old_price", DataType::UInt64),
+        Field::new("product_id", DataType::Int32),
+        Field::new("is_dummy_auction_for_search", DataType::Boolean),
+        Field::new("estimated_total_cost", DataType::UInt64),
+        Field::new("bids_placed_by_winner", DataType::Utf8),
+        Field::new("price_after_promo_discount", DataType::UInt64),
+        Field::new("percent_off_promo", DataType::Utf8),
+        Field::new("penny_started_recording", DataType::Boolean),
+        Field::new("penny_recorded_full", DataType::Boolean),
+    ])
+}
+
+/// Columns of the raw bid frame the processor relies on.
+pub fn bid_schema() -> Schema {
+    Schema::from_iter([
+        Field::new("auction_id", DataType::Int32),
+        Field::new("price", DataType::UInt64),
+        Field::new(
+            "timestamp",
+            DataType::Datetime(TimeUnit::Milliseconds, None),
+        ),
+        Field::new("username", DataType::Utf8),
+    ])
+}
+
+fn same_type(actual: &DataType, expected: &DataType) -> bool {
+    match (actual, expected) {
+        (DataType::Datetime(..), DataType::Datetime(..)) => true,
+        (actual, expected) => actual == expected,
+    }
+}
+
+/// Cast `series` to `dtype`, or `None` when a value would be lost: out of range values would turn
+/// into nulls and fractional floats would be truncated, so the cast must survive the way back.
+fn lossless_cast(series: &Series, dtype: &DataType) -> Option<Series> {
+    let cast = series.strict_cast(dtype).ok()?;
+    let back = cast.cast(series.dtype()).ok()?;
+    back.series_equal_missing(series).then_some(cast)
+}
+
+/// Check `df` against the `expected` and `optional` columns, reporting every problem at once.
+///
+/// Missing and mistyped columns are errors, unknown columns only warnings. With `coerce` set,
+/// numeric columns of the wrong type are cast when no value is lost in the process.
+pub fn validate(
+    mut df: DataFrame,
+    name: &str,
+    expected: &Schema,
+    optional: &Schema,
+    coerce: bool,
+) -> Result<DataFrame, ProcessorError> {
+    let mut problems = Vec::new();
+
+    for (column, dtype) in expected.iter().chain(optional.iter()) {
+        let Ok(series) = df.column(column.as_str()) else {
+            if expected.contains(column.as_str()) {
+                problems.push(format!("missing column `{column}` ({dtype})"));
+            }
+            continue;
+        };
+
+        let actual = series.dtype();
+        if same_type(actual, dtype) {
+            continue;
+        }
+
+        let coerced = (coerce && actual.is_numeric() && dtype.is_numeric())
+            .then(|| lossless_cast(series, dtype))
+            .flatten();
+        match coerced {
+            Some(coerced) => {
+                warn!("{}: cast `{}` from {} to {}", name, column, actual, dtype);
+                df.with_column(coerced)?;
+            }
+            None => problems.push(format!(
+                "column `{column}` has type {actual}, expected {dtype}"
+            )),
+        }
+    }
+
+    for column in df.get_column_names() {
+        if !expected.contains(column) && !optional.contains(column) {
+            warn!("{}: ignoring unexpected column `{}`", name, column);
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(df)
+    } else {
+        Err(ProcessorError::SchemaValidation {
+            name: name.to_owned(),
+            problems,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(result: Result<DataFrame, ProcessorError>) -> Vec<String> {
+        match result {
+            Err(ProcessorError::SchemaValidation { problems, .. }) => problems,
+            other => panic!("expected a schema validation error, got {other:?}"),
+        }
+    }
+
+    fn bids(prices: Series) -> DataFrame {
+        df! [
+            "auction_id" => [1, 1],
+            "price"      => prices,
+            "username"   => ["a", "b"],
+        ]
+        .unwrap()
+    }
+
+    #[test]
+    fn missing_and_mistyped_columns_are_all_reported() {
+        let bdf = bids(Series::new("price", ["1", "2"]));
+
+        let problems = problems(validate(bdf, "bdf", &bid_schema(), &Schema::new(), true));
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("column `price` has type"));
+        assert!(problems[1].starts_with("missing column `timestamp`"));
+    }
+
+    #[test]
+    fn integral_floats_are_coerced() {
+        let expected = Schema::from_iter([Field::new("price", DataType::UInt64)]);
+        let bdf = bids(Series::new("price", [1., 2.]));
+
+        let bdf = validate(bdf, "bdf", &expected, &Schema::new(), true).unwrap();
+        let prices = bdf.column("price").unwrap().u64().unwrap();
+        assert_eq!(prices.into_no_null_iter().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn lossy_casts_are_rejected() {
+        let expected = Schema::from_iter([Field::new("price", DataType::UInt64)]);
+        for prices in [
+            Series::new("price", [1.5, 2.]),
+            Series::new("price", [-1i64, 2]),
+            Series::new("price", [f64::NAN, 2.]),
+        ] {
+            let bdf = bids(prices);
+            let problems = problems(validate(bdf, "bdf", &expected, &Schema::new(), true));
+            assert_eq!(problems.len(), 1, "{problems:?}");
+        }
+    }
+
+    #[test]
+    fn types_are_only_coerced_on_request() {
+        let expected = Schema::from_iter([Field::new("price", DataType::UInt64)]);
+        let bdf = bids(Series::new("price", [1i64, 2]));
+
+        let problems = problems(validate(bdf, "bdf", &expected, &Schema::new(), false));
+        assert_eq!(problems, ["column `price` has type i64, expected u64"]);
+    }
+}
@@ -1,46 +1,140 @@
 use sea_orm::*;
+use std::path::Path;
+use tracing::{debug, warn};
 
-pub(super) async fn set_up_db(db_url: &str, db_name: &str) -> Result<DatabaseConnection, DbErr> {
-    // Attempt to directly connect to the database
-    let db_table = Database::connect(format!("{}/{}", db_url, db_name)).await;
-    if db_table.is_ok() {
-        return db_table;
+use crate::error::ProcessorError;
+
+/// Connect to the database at `db_full_url`, creating it first if it does not exist yet.
+///
+/// Accepts `postgres://host/name`, `mysql://host/name` and `sqlite://path` urls.
+pub(super) async fn set_up_db(
+    db_full_url: &str,
+    recreate: bool,
+) -> Result<DatabaseConnection, ProcessorError> {
+    if let Some(path) = db_full_url.strip_prefix("sqlite:") {
+        return set_up_sqlite(path.trim_start_matches("//"), recreate).await;
     }
 
-    // Directly connecting failed, construct the database then connect to it
-    let db_root = Database::connect(format!("{}/postgres", db_url)).await?;
+    let Some((url, name)) = db_full_url.rsplit_once('/') else {
+        return Err(ProcessorError::DbUrl(db_full_url.to_owned()));
+    };
 
-    let db = match db_root.get_database_backend() {
-        DbBackend::MySql => {
-            db_root
-                .execute(Statement::from_string(
-                    db_root.get_database_backend(),
-                    format!("CREATE DATABASE IF NOT EXISTS `{}`;", db_name),
-                ))
-                .await?;
+    set_up_server_db(url, name, recreate).await
+}
 
-            let url = format!("{}/{}", db_url, db_name);
-            Database::connect(&url).await?
-        }
-        DbBackend::Postgres => {
-            db_root
-                .execute(Statement::from_string(
-                    db_root.get_database_backend(),
-                    format!("DROP DATABASE IF EXISTS \"{}\";", db_name),
-                ))
-                .await?;
-            db_root
-                .execute(Statement::from_string(
-                    db_root.get_database_backend(),
-                    format!("CREATE DATABASE \"{}\";", db_name),
-                ))
-                .await?;
-
-            let url = format!("{}/{}", db_url, db_name);
-            Database::connect(&url).await?
+/// Open (or create) a SQLite database file, `:memory:` opens an in-memory database.
+async fn set_up_sqlite(path: &str, recreate: bool) -> Result<DatabaseConnection, ProcessorError> {
+    let (file, options) = path.split_once('?').unwrap_or((path, ""));
+
+    if recreate && file != ":memory:" && Path::new(file).exists() {
+        warn!("Deleting database {} as requested", file);
+        std::fs::remove_file(file).map_err(|source| ProcessorError::Io {
+            path: file.into(),
+            source,
+        })?;
+    }
+
+    // Let SQLite create the file if it is not there yet
+    let options = if options.contains("mode=") {
+        options.to_owned()
+    } else if options.is_empty() {
+        "mode=rwc".to_owned()
+    } else {
+        format!("{options}&mode=rwc")
+    };
+
+    Database::connect(format!("sqlite://{file}?{options}"))
+        .await
+        .map_err(|source| ProcessorError::DbConnection {
+            database: file.into(),
+            source,
+        })
+}
+
+/// Connect to `db_name` on a database server, creating it first if the server does not have it.
+///
+/// An existing database is only dropped when `recreate` is set. A failed connection to a database
+/// that does exist is reported as a connection error rather than treated as a missing database.
+async fn set_up_server_db(
+    db_url: &str,
+    db_name: &str,
+    recreate: bool,
+) -> Result<DatabaseConnection, ProcessorError> {
+    if !recreate {
+        // Attempt to directly connect to the database
+        match Database::connect(format!("{}/{}", db_url, db_name)).await {
+            Ok(db) => return Ok(db),
+            Err(err) => debug!("Could not connect to {}: {}", db_name, err),
         }
-        DbBackend::Sqlite => db_root,
+    }
+
+    // Connect to the server's maintenance database to find out whether ours exists
+    let db_root = Database::connect(format!("{}/postgres", db_url))
+        .await
+        .map_err(|source| ProcessorError::DbConnection {
+            database: "postgres".into(),
+            source,
+        })?;
+    let backend = db_root.get_database_backend();
+
+    let exists_query = match backend {
+        DbBackend::MySql => Some("SELECT 1 FROM information_schema.schemata WHERE schema_name = ?"),
+        DbBackend::Postgres => Some("SELECT 1 FROM pg_database WHERE datname = $1"),
+        DbBackend::Sqlite => None,
+    };
+    let Some(exists_query) = exists_query else {
+        return Ok(db_root);
+    };
+
+    let exists = db_root
+        .query_one(Statement::from_sql_and_values(
+            backend,
+            exists_query,
+            [db_name.into()],
+        ))
+        .await?
+        .is_some();
+
+    if exists && !recreate {
+        // The database is there, so the direct connection failed for another reason
+        return connect(db_url, db_name).await;
+    }
+
+    let (drop, create) = match backend {
+        DbBackend::MySql => (
+            format!("DROP DATABASE IF EXISTS `{}`;", db_name),
+            format!("CREATE DATABASE IF NOT EXISTS `{}`;", db_name),
+        ),
+        _ => (
+            format!("DROP DATABASE IF EXISTS \"{}\";", db_name),
+            format!("CREATE DATABASE \"{}\";", db_name),
+        ),
     };
 
-    Ok(db)
+    if exists {
+        warn!("Dropping database {} as requested", db_name);
+        db_root
+            .execute(Statement::from_string(backend, drop))
+            .await?;
+    }
+
+    debug!("Creating database {}", db_name);
+    db_root
+        .execute(Statement::from_string(backend, create))
+        .await
+        .map_err(|source| ProcessorError::DbMissing {
+            database: db_name.into(),
+            source,
+        })?;
+
+    connect(db_url, db_name).await
+}
+
+async fn connect(db_url: &str, db_name: &str) -> Result<DatabaseConnection, ProcessorError> {
+    Database::connect(format!("{}/{}", db_url, db_name))
+        .await
+        .map_err(|source| ProcessorError::DbConnection {
+            database: db_name.into(),
+            source,
+        })
 }
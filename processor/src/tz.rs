@@ -0,0 +1,168 @@
+use polars::prelude::*;
+
+/// Timezone every timestamp is stored in once it leaves the database.
+pub const UTC: &str = "UTC";
+
+/// Check that `name` is a timezone from the IANA database, e.g. `America/New_York`.
+pub fn is_valid(name: &str) -> bool {
+    name.parse::<chrono_tz::Tz>().is_ok()
+}
+
+/// Convert the datetime `columns` of `df` to UTC.
+///
+/// Naive columns are read as wall clock times in `source`. Times repeated when the clocks go back
+/// resolve to their first occurrence, times skipped when the clocks go forward are an error.
+/// Columns that already carry a timezone are only converted.
+pub fn frame_to_utc(df: DataFrame, columns: &[&str], source: &str) -> PolarsResult<DataFrame> {
+    let exprs = columns
+        .iter()
+        .map(|&name| {
+            let dtype = df.column(name)?.dtype();
+            Ok(match dtype {
+                DataType::Datetime(_, None) => col(name)
+                    .dt()
+                    .replace_time_zone(Some(source.into()), Some(true))
+                    .dt()
+                    .convert_time_zone(UTC.into()),
+                DataType::Datetime(_, Some(_)) => col(name).dt().convert_time_zone(UTC.into()),
+                dtype => polars_bail!(SchemaMismatch: "column `{}` has type {}, expected a datetime", name, dtype),
+            })
+        })
+        .collect::<PolarsResult<Vec<Expr>>>()?;
+
+    df.lazy().with_columns(exprs).collect()
+}
+
+/// Convert the datetime `columns` of `df` to naive wall clock times in `target`, the inverse of
+/// [`frame_to_utc`]. Naive columns are assumed to be in `target` already.
+pub fn frame_to_local(df: DataFrame, columns: &[&str], target: &str) -> PolarsResult<DataFrame> {
+    let exprs = columns
+        .iter()
+        .filter(|&&name| {
+            matches!(
+                df.column(name).map(|s| s.dtype()),
+                Ok(DataType::Datetime(_, Some(_)))
+            )
+        })
+        .map(|&name| {
+            col(name)
+                .dt()
+                .convert_time_zone(target.into())
+                .dt()
+                .replace_time_zone(None, None)
+        })
+        .collect::<Vec<Expr>>();
+
+    df.lazy().with_columns(exprs).collect()
+}
+
+/// Wall clock time of the UTC `expr` in `target`, for time of day features.
+pub fn local(expr: Expr, target: &str) -> Expr {
+    expr.dt().convert_time_zone(target.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::*;
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .and_then(|d| d.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    fn utc_values(df: &DataFrame, name: &str) -> Vec<NaiveDateTime> {
+        let ca = df.column(name).unwrap().datetime().unwrap();
+        assert_eq!(ca.time_zone().as_deref(), Some(UTC));
+        ca.as_datetime_iter().map(Option::unwrap).collect()
+    }
+
+    #[test]
+    fn naive_times_are_read_in_the_source_zone() {
+        let df = df! [
+            // EST before the spring transition, EDT after it
+            "timestamp" => [at(3, 12, 1, 30), at(3, 12, 3, 30)],
+        ]
+        .unwrap();
+
+        let df = frame_to_utc(df, &["timestamp"], "America/New_York").unwrap();
+        assert_eq!(
+            utc_values(&df, "timestamp"),
+            [at(3, 12, 6, 30), at(3, 12, 7, 30)]
+        );
+    }
+
+    #[test]
+    fn repeated_hour_resolves_to_first_occurrence() {
+        let df = df! [
+            "timestamp" => [at(11, 5, 0, 59), at(11, 5, 1, 30), at(11, 5, 2, 0)],
+        ]
+        .unwrap();
+
+        let df = frame_to_utc(df, &["timestamp"], "America/New_York").unwrap();
+        assert_eq!(
+            utc_values(&df, "timestamp"),
+            [at(11, 5, 4, 59), at(11, 5, 5, 30), at(11, 5, 7, 0)]
+        );
+    }
+
+    #[test]
+    fn skipped_hour_is_an_error() {
+        let df = df! [
+            "timestamp" => [at(3, 12, 2, 30)],
+        ]
+        .unwrap();
+
+        assert!(frame_to_utc(df, &["timestamp"], "America/New_York").is_err());
+    }
+
+    #[test]
+    fn local_round_trips_through_utc() {
+        let times = [at(3, 12, 1, 30), at(3, 12, 3, 30), at(11, 5, 1, 30)];
+        let df = df! [ "timestamp" => times ].unwrap();
+
+        let utc = frame_to_utc(df, &["timestamp"], "America/New_York").unwrap();
+        let local = frame_to_local(utc, &["timestamp"], "America/New_York").unwrap();
+        let ca = local.column("timestamp").unwrap().datetime().unwrap();
+        assert_eq!(ca.time_zone(), &None);
+        assert_eq!(
+            ca.as_datetime_iter()
+                .map(Option::unwrap)
+                .collect::<Vec<_>>(),
+            times
+        );
+    }
+
+    #[test]
+    fn hour_follows_the_site_zone_across_transitions() {
+        let df = df! [
+            "start_time" => [at(3, 12, 6, 30), at(3, 12, 7, 30)],
+        ]
+        .unwrap();
+
+        let df = frame_to_utc(df, &["start_time"], UTC)
+            .unwrap()
+            .lazy()
+            .select([local(col("start_time"), "America/New_York").dt().hour()])
+            .collect()
+            .unwrap();
+        let hours = df
+            .column("start_time")
+            .unwrap()
+            .cast(&DataType::UInt32)
+            .unwrap();
+        assert_eq!(
+            hours.u32().unwrap().into_no_null_iter().collect::<Vec<_>>(),
+            [1, 3]
+        );
+    }
+
+    #[test]
+    fn zone_names_are_checked() {
+        assert!(is_valid("Europe/Amsterdam"));
+        assert!(is_valid(UTC));
+        assert!(!is_valid("Mars/Olympus_Mons"));
+    }
+}